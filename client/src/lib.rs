@@ -1 +1,31 @@
-tonic::include_proto!("orderbook");
\ No newline at end of file
+tonic::include_proto!("orderbook");
+
+use level_change::Action;
+
+/// Applies `BookUpdates` diff to the locally rebuilt book
+pub fn apply_diff(book: &mut Summary, diff: &BookDiff) -> Result<(), String> {
+    apply_changes(&mut book.bids, &diff.bids)?;
+    apply_changes(&mut book.asks, &diff.asks)?;
+    book.spread = diff.spread;
+    Ok(())
+}
+
+fn apply_changes(side: &mut Vec<Level>, changes: &[LevelChange]) -> Result<(), String> {
+    for c in changes {
+        let idx = c.index as usize;
+        let action = Action::try_from(c.action).map_err(|_| format!("Unknown action {}", c.action))?;
+        match action {
+            Action::Insert if idx <= side.len() => {
+                side.insert(idx, c.level.clone().ok_or("Insert without level")?)
+            }
+            Action::Update if idx < side.len() => {
+                side[idx] = c.level.clone().ok_or("Update without level")?
+            }
+            Action::Delete if idx < side.len() => {
+                side.remove(idx);
+            }
+            _ => return Err(format!("{:?} index {} out of range {}", action, idx, side.len())),
+        }
+    }
+    Ok(())
+}
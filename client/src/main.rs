@@ -1,8 +1,8 @@
 use std::str::FromStr;
 
 use clap::{Command, Arg};
-use tonic::transport::{Channel, Uri};
-use client::orderbook_aggregator_client::OrderbookAggregatorClient;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity, Uri};
+use client::{book_update, orderbook_aggregator_client::OrderbookAggregatorClient};
 
 #[tokio::main]
 async fn main() {
@@ -17,15 +17,310 @@ async fn main() {
             .takes_value(true)
             .required(true),
     )
+    .arg(
+        Arg::new("depth")
+            .help("Levels per side, server maximum by default")
+            .short('d')
+            .takes_value(true),
+    )
+    .arg(
+        Arg::new("instrument")
+            .help("Instrument name, server default if not set")
+            .short('i')
+            .takes_value(true),
+    )
+    .arg(
+        Arg::new("exchanges")
+            .help("Comma separated exchanges to include, e.g. binance,bitstamp")
+            .short('e')
+            .takes_value(true),
+    )
+    .arg(
+        Arg::new("aggregate")
+            .help("Level aggregation: price, tick:<width> or bps:<width>")
+            .short('g')
+            .long("aggregate")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::new("token")
+            .help("API key sent as bearer token")
+            .short('t')
+            .long("token")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::new("ids")
+            .help("Identify exchanges by exchange_id only, without name strings")
+            .long("ids"),
+    )
+    .arg(
+        Arg::new("list")
+            .help("Print tracked exchanges and instruments and exit")
+            .long("list"),
+    )
+    .arg(
+        Arg::new("opportunities")
+            .help("Stream cross-exchange arbitrage opportunities")
+            .long("opportunities"),
+    )
+    .arg(
+        Arg::new("quote")
+            .help("Print execution cost quote and exit, e.g. buy:5 or sell:0.5")
+            .long("quote")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::new("notional")
+            .help("Quote amount is quote currency notional")
+            .long("notional")
+            .requires("quote"),
+    )
+    .arg(
+        Arg::new("fees")
+            .help("Include taker fees in quote")
+            .long("fees")
+            .requires("quote"),
+    )
+    .arg(
+        Arg::new("full_depth")
+            .help("Quote against full exchange books")
+            .long("full-depth")
+            .requires("quote"),
+    )
+    .arg(
+        Arg::new("simulate")
+            .help("Simulate routing a parent order, e.g. buy:5; market order unless --limit is set")
+            .long("simulate")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::new("limit")
+            .help("Limit price of the simulated order")
+            .long("limit")
+            .takes_value(true)
+            .requires("simulate"),
+    )
+    .arg(
+        Arg::new("ioc")
+            .help("Cancel the simulated order remainder instead of resting it")
+            .long("ioc")
+            .requires("limit"),
+    )
+    .arg(
+        Arg::new("metrics")
+            .help("Stream market metrics, at most one message per given milliseconds")
+            .long("metrics")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::new("trades")
+            .help("Stream trades of the selected exchanges")
+            .long("trades"),
+    )
+    .arg(
+        Arg::new("bbo")
+            .help("Stream merged real-time best bid and offer of the selected exchanges")
+            .long("bbo"),
+    )
+    .arg(
+        Arg::new("bars")
+            .help("Stream time bars of given interval in seconds")
+            .long("bars")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::new("history")
+            .help("Print given number of most recent closed bars instead of streaming, 0 for all")
+            .long("history")
+            .takes_value(true)
+            .requires("bars"),
+    )
+    .arg(
+        Arg::new("diff")
+            .help("Use incremental BookUpdates stream and verify rebuilt book against snapshots")
+            .long("diff"),
+    )
+    .arg(
+        Arg::new("tls")
+            .help("Connect using TLS, server certificate verified against system roots")
+            .long("tls"),
+    )
+    .arg(
+        Arg::new("ca")
+            .help("PEM CA bundle to verify server certificate with, implies --tls")
+            .long("ca")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::new("domain")
+            .help("Expected server certificate name, defaults to address host")
+            .long("domain")
+            .takes_value(true),
+    )
+    .arg(
+        Arg::new("cert")
+            .help("PEM client certificate for mutual TLS")
+            .long("cert")
+            .takes_value(true)
+            .requires("key"),
+    )
+    .arg(
+        Arg::new("key")
+            .help("PEM client private key for mutual TLS")
+            .long("key")
+            .takes_value(true)
+            .requires("cert"),
+    )
     .get_matches();
 
     let addr_str = matches.value_of("server_addr").unwrap();
+    let tls = matches.is_present("tls") || matches.is_present("ca") || matches.is_present("cert");
+    let scheme = if tls { "https" } else { "http" };
+
+    let mut endpoint = Channel::builder(Uri::from_str(&format!("{}://{}", scheme, addr_str)
+    ).expect("Uri parse error"));
+
+    if tls {
+        let mut tls_config = ClientTlsConfig::new();
+        if let Some(ca) = matches.value_of("ca") {
+            let pem = std::fs::read(ca).expect("Failed to read CA bundle");
+            tls_config = tls_config.ca_certificate(Certificate::from_pem(pem));
+        }
+        if let Some(domain) = matches.value_of("domain") {
+            tls_config = tls_config.domain_name(domain);
+        }
+        if let (Some(cert), Some(key)) = (matches.value_of("cert"), matches.value_of("key")) {
+            let cert = std::fs::read(cert).expect("Failed to read client certificate");
+            let key = std::fs::read(key).expect("Failed to read client key");
+            tls_config = tls_config.identity(Identity::from_pem(cert, key));
+        }
+        endpoint = endpoint.tls_config(tls_config).expect("Invalid TLS configuration");
+    }
 
     let mut client = OrderbookAggregatorClient::new(
-        Channel::builder(Uri::from_str(&format!("https://{}",addr_str)
-    ).expect("Uri parse error")).connect().await.expect("Failed to connect"));
+        endpoint.connect().await.expect("Failed to connect"));
+
+    let token = matches.value_of("token");
+    let req = with_token(client::BookRequest {
+        depth: matches.value_of("depth").map(|d| d.parse().expect("Invalid depth")).unwrap_or(0),
+        instrument: matches.value_of("instrument").unwrap_or_default().to_string(),
+        omit_exchange_names: matches.is_present("ids"),
+        exchanges: matches.value_of("exchanges").unwrap_or_default().split(',').filter(|e| !e.is_empty())
+            .map(|e| client::ExchangeId::from_str_name(&e.to_uppercase()).expect("Unknown exchange") as i32)
+            .collect(),
+        aggregation: matches.value_of("aggregate").map(parse_aggregation),
+    }, token);
+    if matches.is_present("list") {
+        let exchanges = client.list_exchanges(with_token(client::Empty {}, token)).await.expect("Failed to list exchanges");
+        for e in exchanges.into_inner().exchanges {
+            println!("{:?}", e);
+        }
+        let instruments = client.list_instruments(with_token(client::Empty {}, token)).await.expect("Failed to list instruments");
+        for i in instruments.into_inner().instruments {
+            println!("{:?}", i);
+        }
+        return;
+    }
+    if let Some(q) = matches.value_of("quote") {
+        let (side, amount) = q.split_once(':').expect("Quote format is <side>:<amount>");
+        let side = client::Side::from_str_name(&side.to_uppercase()).expect("Unknown side");
+        let amount: f64 = amount.parse().expect("Invalid quote amount");
+        let notional = matches.is_present("notional");
+        let resp = client.quote(with_token(client::QuoteRequest {
+            side: side as i32,
+            quantity: if notional { 0.0 } else { amount },
+            notional: if notional { amount } else { 0.0 },
+            include_fees: matches.is_present("fees"),
+            full_depth: matches.is_present("full_depth"),
+            exchanges: req.get_ref().exchanges.clone(),
+        }, token)).await.expect("Quote failed");
+        println!("{:?}", resp.into_inner());
+        return;
+    }
+    if let Some(o) = matches.value_of("simulate") {
+        let (side, quantity) = o.split_once(':').expect("Order format is <side>:<quantity>");
+        let side = client::Side::from_str_name(&side.to_uppercase()).expect("Unknown side");
+        let limit_price: Option<f64> = matches.value_of("limit").map(|p| p.parse().expect("Invalid limit price"));
+        let order_type = match (limit_price, matches.is_present("ioc")) {
+            (None, _) => client::OrderType::Market,
+            (Some(_), false) => client::OrderType::Limit,
+            (Some(_), true) => client::OrderType::Ioc,
+        };
+        let mut stream = client.simulate_order(with_token(client::SimulationRequest {
+            side: side as i32,
+            r#type: order_type as i32,
+            quantity: quantity.parse().expect("Invalid quantity"),
+            limit_price: limit_price.unwrap_or_default(),
+            exchanges: req.get_ref().exchanges.clone(),
+            timeout_secs: 0,
+        }, token)).await.expect("Simulation failed").into_inner();
+        while let Some(report) = stream.message().await.expect("Stream error") {
+            println!("{:?}", report);
+        }
+        return;
+    }
+    if let Some(interval) = matches.value_of("metrics") {
+        let mut stream = client.metrics(with_token(client::MetricsRequest {
+            interval_ms: interval.parse().expect("Invalid metrics interval"),
+        }, token)).await.expect("Failed to subscribe").into_inner();
+        while let Some(m) = stream.message().await.expect("Stream error") {
+            println!("{:?}", m);
+        }
+        return;
+    }
+    if matches.is_present("trades") {
+        let mut stream = client.trades(with_token(client::TradesRequest {
+            exchanges: req.get_ref().exchanges.clone(),
+        }, token)).await.expect("Failed to subscribe").into_inner();
+        while let Some(trade) = stream.message().await.expect("Stream error") {
+            println!("{:?}", trade);
+        }
+        return;
+    }
+    if matches.is_present("bbo") {
+        let mut stream = client.best_bid_offer(with_token(client::BboRequest {
+            exchanges: req.get_ref().exchanges.clone(),
+        }, token)).await.expect("Failed to subscribe").into_inner();
+        while let Some(bbo) = stream.message().await.expect("Stream error") {
+            println!("Bid: {} x {} ({}) Ask: {} x {} ({}) Spread: {}",
+                bbo.bid_price, bbo.bid_quantity, bbo.bid_exchange_id,
+                bbo.ask_price, bbo.ask_quantity, bbo.ask_exchange_id, bbo.spread);
+        }
+        return;
+    }
+    if let Some(interval) = matches.value_of("bars") {
+        let bars_req = client::BarsRequest {
+            interval_secs: interval.parse().expect("Invalid bar interval"),
+            limit: matches.value_of("history").map(|l| l.parse().expect("Invalid history limit")).unwrap_or_default(),
+        };
+        if matches.is_present("history") {
+            let history = client.bar_history(with_token(bars_req, token)).await.expect("Failed to get bars").into_inner();
+            for bar in history.bars {
+                println!("{:?}", bar);
+            }
+            return;
+        }
+        let mut stream = client.bars(with_token(bars_req, token)).await.expect("Failed to subscribe").into_inner();
+        while let Some(bar) = stream.message().await.expect("Stream error") {
+            println!("{:?}", bar);
+        }
+        return;
+    }
+    if matches.is_present("opportunities") {
+        let mut stream = client.opportunities(with_token(client::Empty {}, token)).await.expect("Failed to subscribe").into_inner();
+        while let Some(o) = stream.message().await.expect("Stream error") {
+            println!("{:?}", o);
+        }
+        return;
+    }
+
+    if matches.is_present("diff") {
+        let mut stream = client.book_updates(req).await.expect("Failed to get stream").into_inner();
+        follow_updates(&mut stream).await;
+        return;
+    }
 
-    let req = tonic::Request::new(client::Empty{});
     let mut stream = client.book_summary(req).await.expect("Failed to get stream").into_inner();
 
     loop  {
@@ -33,6 +328,9 @@ async fn main() {
             Ok(msg) => {
                 if let Some (m) = msg {
                     println!("Summary:\nSpread: {}\nBids: {:?}\nAsks: {:?}", m.spread, m.bids, m.asks);
+                    if let Some(l) = m.latency {
+                        println!("Latency: {:?}", l);
+                    }
                 } else {
                     println!("Stream ended");
                     break;
@@ -46,3 +344,85 @@ async fn main() {
         }
     }
 }
+
+/// Wraps message into request with optional bearer token
+fn with_token<T>(msg: T, token: Option<&str>) -> tonic::Request<T> {
+    let mut req = tonic::Request::new(msg);
+    if let Some(token) = token {
+        req.metadata_mut().insert(
+            "authorization",
+            format!("Bearer {}", token).parse().expect("Invalid token"),
+        );
+    }
+    req
+}
+
+/// Rebuilds the book from `BookUpdates` stream, checking sequence numbers
+/// and comparing the rebuilt book with every snapshot checkpoint
+async fn follow_updates(stream: &mut tonic::Streaming<client::BookUpdate>) {
+    let mut book: Option<client::Summary> = None;
+    let mut sequence = 0;
+    loop {
+        let msg = match stream.message().await {
+            Ok(Some(m)) => m,
+            Ok(None) => {
+                println!("Stream ended");
+                break;
+            }
+            Err(e) => {
+                eprintln!("Streamming error: {}", e);
+                break;
+            }
+        };
+
+        if msg.sequence != sequence + 1 {
+            eprintln!("Sequence gap: expected {}, got {}", sequence + 1, msg.sequence);
+        }
+        sequence = msg.sequence;
+
+        match msg.update {
+            Some(book_update::Update::Snapshot(snapshot)) => {
+                if let Some(b) = &book {
+                    if b.bids == snapshot.bids && b.asks == snapshot.asks {
+                        println!("Checkpoint {}: rebuilt book verified", sequence);
+                    } else {
+                        eprintln!("Checkpoint {}: rebuilt book differs from snapshot", sequence);
+                    }
+                }
+                book = Some(snapshot);
+            }
+            Some(book_update::Update::Diff(diff)) => {
+                let b = match book.as_mut() {
+                    Some(b) => b,
+                    None => {
+                        eprintln!("Diff received before snapshot");
+                        continue;
+                    }
+                };
+                if let Err(e) = client::apply_diff(b, &diff) {
+                    eprintln!("Failed to apply diff {}: {}", sequence, e);
+                    book = None;
+                    continue;
+                }
+            }
+            None => continue,
+        }
+
+        if let Some(m) = &book {
+            println!("Summary:\nSpread: {}\nBids: {:?}\nAsks: {:?}", m.spread, m.bids, m.asks);
+        }
+    }
+}
+
+/// Parses `price`, `tick:<width>` or `bps:<width>`
+fn parse_aggregation(s: &str) -> client::Aggregation {
+    use client::aggregation::Mode;
+    let (mode, width) = s.split_once(':').unwrap_or((s, ""));
+    let width = || width.parse().expect("Invalid aggregation width");
+    match mode {
+        "price" => client::Aggregation { mode: Mode::Price as i32, ..Default::default() },
+        "tick" => client::Aggregation { mode: Mode::Tick as i32, tick: width(), ..Default::default() },
+        "bps" => client::Aggregation { mode: Mode::Tick as i32, tick_bps: width(), ..Default::default() },
+        _ => panic!("Unknown aggregation {}", s),
+    }
+}
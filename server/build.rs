@@ -1,6 +1,13 @@
+use std::path::PathBuf;
+
 fn main() {
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").expect("OUT_DIR is set"));
     tonic_build::configure()
         .build_server(true)
+        .build_client(false)
+        // JSON output of WebSocket gateway
+        .type_attribute(".", "#[derive(serde::Serialize)]")
+        .file_descriptor_set_path(out_dir.join("orderbook_descriptor.bin"))
         .compile(&["../common/proto/orderbook.proto"], &["../common/proto/"])
         .expect("Failed to build direct messages proto/gRPC definition");
 }
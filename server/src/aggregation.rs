@@ -0,0 +1,165 @@
+use std::convert::TryFrom;
+
+use crate::server::{aggregation::Mode, Aggregation, ExchangeId, Level, Summary, VenueAmount};
+
+/// Level aggregation selected by a subscriber
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LevelAggregation {
+    /// Equal prices across exchanges
+    Price,
+    /// Fixed bucket width in quote currency
+    Tick(f64),
+    /// Bucket width in basis points of the mid price
+    TickBps(f64),
+}
+
+impl LevelAggregation {
+    /// Aggregation requested by the client, `None` for separate levels
+    pub fn from_request(req: &Aggregation) -> Result<Option<Self>, tonic::Status> {
+        let valid = |w: f64| w.is_finite() && w > 0.0;
+        match Mode::try_from(req.mode) {
+            Ok(Mode::None) => Ok(None),
+            Ok(Mode::Price) => Ok(Some(Self::Price)),
+            Ok(Mode::Tick) if valid(req.tick) => Ok(Some(Self::Tick(req.tick))),
+            Ok(Mode::Tick) if req.tick == 0.0 && valid(req.tick_bps) => {
+                Ok(Some(Self::TickBps(req.tick_bps)))
+            }
+            Ok(Mode::Tick) => Err(tonic::Status::invalid_argument(
+                "Tick aggregation requires positive tick or tick_bps",
+            )),
+            Err(_) => Err(tonic::Status::invalid_argument(format!(
+                "Unknown aggregation mode {}",
+                req.mode
+            ))),
+        }
+    }
+
+    /// Aggregates both sides of the summary, spread is left unchanged
+    pub fn apply(self, summary: &mut Summary) {
+        let width = match self {
+            Self::Price => None,
+            Self::Tick(tick) => Some(tick),
+            Self::TickBps(bps) => {
+                let mid = match (summary.bids.first(), summary.asks.first()) {
+                    (Some(bid), Some(ask)) => (bid.price + ask.price) / 2.0,
+                    (Some(l), None) | (None, Some(l)) => l.price,
+                    (None, None) => return,
+                };
+                Some(mid * bps / 10_000.0)
+            }
+        };
+        summary.bids = aggregate_side(&summary.bids, width, true);
+        summary.asks = aggregate_side(&summary.asks, width, false);
+    }
+}
+
+/// Bucket price, rounded away from the spread
+fn bucket(price: f64, width: Option<f64>, bid: bool) -> f64 {
+    match width {
+        Some(w) => {
+            // Avoid 10.0 / 0.01 = 999.999.. falling into the lower bucket
+            let n = (price / w * 1e9).round() / 1e9;
+            let n = if bid { n.floor() } else { n.ceil() };
+            n * w
+        }
+        None => price,
+    }
+}
+
+/// Combines adjacent levels of a sorted side falling into the same bucket
+fn aggregate_side(levels: &[Level], width: Option<f64>, bid: bool) -> Vec<Level> {
+    let mut out: Vec<Level> = Vec::with_capacity(levels.len());
+    for level in levels {
+        let price = bucket(level.price, width, bid);
+        let venue = VenueAmount {
+            exchange_id: level.exchange_id,
+            amount: level.amount,
+        };
+        match out.last_mut() {
+            Some(last) if last.price == price => {
+                let amount = last.amount + level.amount;
+                if amount > 0.0 {
+                    last.raw_price =
+                        (last.raw_price * last.amount + level.raw_price * level.amount) / amount;
+                    last.original_price = (last.original_price * last.amount
+                        + level.original_price * level.amount)
+                        / amount;
+                }
+                if last.original_quote != level.original_quote {
+                    // No single original price across quote currencies
+                    last.original_quote.clear();
+                    last.original_price = 0.0;
+                }
+                last.amount = amount;
+                if last.exchange_id != level.exchange_id {
+                    last.exchange_id = ExchangeId::ExchangeUnspecified as i32;
+                    last.exchange.clear();
+                }
+                match last
+                    .venues
+                    .iter_mut()
+                    .find(|v| v.exchange_id == venue.exchange_id)
+                {
+                    Some(v) => v.amount += venue.amount,
+                    None => last.venues.push(venue),
+                }
+            }
+            _ => out.push(Level {
+                price,
+                venues: vec![venue],
+                ..level.clone()
+            }),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LevelAggregation;
+    use crate::server::{ExchangeId, Summary};
+    use crate::test_util::level;
+
+    #[test]
+    fn test_aggregate() {
+        let summary = Summary {
+            spread: 0.5,
+            bids: vec![
+                level(ExchangeId::Binance, 10.0, 1.0),
+                level(ExchangeId::Bitstamp, 10.0, 2.0),
+                level(ExchangeId::Binance, 9.7, 1.0),
+                level(ExchangeId::Binance, 9.2, 1.0),
+            ],
+            asks: vec![
+                level(ExchangeId::Bitstamp, 10.5, 1.0),
+                level(ExchangeId::Binance, 10.9, 3.0),
+            ],
+            ..Default::default()
+        };
+
+        let mut by_price = summary.clone();
+        LevelAggregation::Price.apply(&mut by_price);
+        assert_eq!(by_price.bids.len(), 3);
+        assert_eq!(by_price.bids[0].amount, 3.0);
+        assert_eq!(
+            by_price.bids[0].exchange_id,
+            ExchangeId::ExchangeUnspecified as i32
+        );
+        assert_eq!(by_price.bids[0].venues.len(), 2);
+        assert_eq!(by_price.bids[1].exchange_id, ExchangeId::Binance as i32);
+        assert_eq!(by_price.asks.len(), 2);
+
+        let mut by_tick = summary;
+        LevelAggregation::Tick(1.0).apply(&mut by_tick);
+        assert_eq!(by_tick.spread, 0.5);
+        assert_eq!(by_tick.bids.len(), 2);
+        assert_eq!(by_tick.bids[0].price, 10.0);
+        assert_eq!(by_tick.bids[1].price, 9.0);
+        assert_eq!(by_tick.bids[1].amount, 2.0);
+        assert_eq!(by_tick.bids[1].venues.len(), 1);
+        assert!((by_tick.bids[1].raw_price - 9.45).abs() < 1e-9);
+        assert_eq!(by_tick.asks.len(), 1);
+        assert_eq!(by_tick.asks[0].price, 11.0);
+        assert_eq!(by_tick.asks[0].amount, 4.0);
+    }
+}
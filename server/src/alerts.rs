@@ -0,0 +1,529 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+use crate::config::{AlertAction, AlertCondition, AlertRule, AlertsConfig, BookSide, ServerConfig};
+use crate::latency::now_us;
+use crate::registry::ExchangeRegistry;
+use crate::server::Summary;
+use crate::{Exchange, OrderBook, TrackerError};
+
+/// Fired alert, webhook payload
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub rule: String,
+    pub message: String,
+    /// Microseconds since UNIX epoch
+    pub timestamp_us: u64,
+}
+
+/// Condition with exchanges resolved and times in microseconds
+#[derive(Debug, Clone, Copy)]
+enum Condition {
+    SpreadBelow(f64),
+    SpreadAbove(f64),
+    Stale(Exchange, u64),
+    MidMove {
+        fraction: f64,
+        window_us: u64,
+    },
+    Divergence {
+        a: Exchange,
+        b: Exchange,
+        side: BookSide,
+        bps: f64,
+    },
+}
+
+impl Condition {
+    fn new(c: &AlertCondition) -> Result<Self, TrackerError> {
+        let exchange = |name: &str| {
+            Exchange::from_str(name)
+                .map_err(|_| TrackerError::Config(format!("Unknown alert exchange {}", name)))
+        };
+        Ok(match c {
+            AlertCondition::SpreadBelow { value } => Condition::SpreadBelow(*value),
+            AlertCondition::SpreadAbove { value } => Condition::SpreadAbove(*value),
+            AlertCondition::Stale { exchange: e, secs } => {
+                Condition::Stale(exchange(e)?, (secs * 1_000_000.0) as u64)
+            }
+            AlertCondition::MidMove {
+                percent,
+                window_secs,
+            } => Condition::MidMove {
+                fraction: percent / 100.0,
+                window_us: window_secs * 1_000_000,
+            },
+            AlertCondition::Divergence {
+                exchanges: [a, b], ..
+            } if exchange(a)? == exchange(b)? => {
+                return Err(TrackerError::Config(format!(
+                    "Divergence of {} with itself",
+                    a
+                )))
+            }
+            AlertCondition::Divergence {
+                exchanges: [a, b],
+                side,
+                bps,
+            } => Condition::Divergence {
+                a: exchange(a)?,
+                b: exchange(b)?,
+                side: *side,
+                bps: *bps,
+            },
+        })
+    }
+}
+
+/// Problems of alert rules: unknown exchanges and duplicate names
+pub fn check_rules(cfg: &AlertsConfig) -> Vec<String> {
+    let mut problems = vec![];
+    for (i, rule) in cfg.rules.iter().enumerate() {
+        if let Err(e) = Condition::new(&rule.condition) {
+            problems.push(format!("alerts rule {}: {}", rule.name, e));
+        }
+        if cfg.rules[..i].iter().any(|r| r.name == rule.name) {
+            problems.push(format!(
+                "alerts rule {} is defined more than once",
+                rule.name
+            ));
+        }
+    }
+    problems
+}
+
+/// Data rules are evaluated against
+struct Inputs<'a> {
+    summary: &'a Summary,
+    books: &'a [OrderBook],
+    /// Last update time, indexed by `Exchange`
+    last_update_us: &'a [u64],
+}
+
+struct RuleState {
+    rule: AlertRule,
+    condition: Condition,
+    /// Condition holds since
+    since: Option<u64>,
+    /// Fired and not resolved yet
+    active: bool,
+    last_fired: Option<u64>,
+}
+
+/// Rules and their firing state
+struct RuleSet {
+    rules: Vec<RuleState>,
+    /// Merged mid samples of the longest mid move window
+    mids: VecDeque<(u64, f64)>,
+    mid_window_us: u64,
+    started_us: u64,
+}
+
+impl RuleSet {
+    fn new(cfg: &AlertsConfig, now: u64) -> Result<Self, TrackerError> {
+        let rules = cfg
+            .rules
+            .iter()
+            .map(|rule| {
+                Ok(RuleState {
+                    condition: Condition::new(&rule.condition)?,
+                    rule: rule.clone(),
+                    since: None,
+                    active: false,
+                    last_fired: None,
+                })
+            })
+            .collect::<Result<Vec<_>, TrackerError>>()?;
+        let mid_window_us = rules
+            .iter()
+            .filter_map(|r| match r.condition {
+                Condition::MidMove { window_us, .. } => Some(window_us),
+                _ => None,
+            })
+            .max()
+            .unwrap_or_default();
+        Ok(Self {
+            rules,
+            mids: VecDeque::new(),
+            mid_window_us,
+            started_us: now,
+        })
+    }
+
+    /// Rule set of a reloaded configuration, unchanged rules keep their
+    /// firing state and mid samples are kept
+    fn reconfigure(&self, cfg: &AlertsConfig) -> Result<Self, TrackerError> {
+        let mut next = Self::new(cfg, self.started_us)?;
+        for state in next.rules.iter_mut() {
+            if let Some(old) = self.rules.iter().find(|r| r.rule == state.rule) {
+                state.since = old.since;
+                state.active = old.active;
+                state.last_fired = old.last_fired;
+            }
+        }
+        next.mids = self.mids.clone();
+        Ok(next)
+    }
+
+    /// Alerts to fire now, an active alert fires again only after it resolves
+    /// and its cooldown has passed
+    fn evaluate(&mut self, inputs: &Inputs, now: u64) -> Vec<Alert> {
+        let best = |levels: &[crate::server::Level]| levels.first().map(|l| l.raw_price);
+        let mid = best(&inputs.summary.bids)
+            .zip(best(&inputs.summary.asks))
+            .map(|(bid, ask)| (bid + ask) / 2.0);
+        if self.mid_window_us > 0 {
+            if let Some(mid) = mid {
+                self.mids.push_back((now, mid));
+            }
+            let from = now.saturating_sub(self.mid_window_us);
+            while self.mids.front().map(|m| m.0 < from).unwrap_or(false) {
+                self.mids.pop_front();
+            }
+        }
+
+        let mut fired = vec![];
+        for i in 0..self.rules.len() {
+            let message = self.check(self.rules[i].condition, inputs, mid, now);
+            let state = &mut self.rules[i];
+            match message {
+                Some(message) => {
+                    let since = *state.since.get_or_insert(now);
+                    let held = now.saturating_sub(since) >= state.rule.for_ms * 1000;
+                    let cooled = state
+                        .last_fired
+                        .map(|t| now.saturating_sub(t) >= state.rule.cooldown_secs * 1_000_000)
+                        .unwrap_or(true);
+                    if !state.active && held && cooled {
+                        state.active = true;
+                        state.last_fired = Some(now);
+                        fired.push(Alert {
+                            rule: state.rule.name.clone(),
+                            message,
+                            timestamp_us: now,
+                        });
+                    }
+                }
+                None => {
+                    if state.active {
+                        info!(rule = %state.rule.name, "Alert resolved");
+                    }
+                    state.since = None;
+                    state.active = false;
+                }
+            }
+        }
+        fired
+    }
+
+    /// Alert message if the condition holds
+    fn check(&self, c: Condition, inputs: &Inputs, mid: Option<f64>, now: u64) -> Option<String> {
+        let summary = inputs.summary;
+        let valid = !summary.bids.is_empty() && !summary.asks.is_empty();
+        match c {
+            Condition::SpreadBelow(value) => (valid && summary.spread < value)
+                .then(|| format!("Spread {} below {}", summary.spread, value)),
+            Condition::SpreadAbove(value) => (valid && summary.spread > value)
+                .then(|| format!("Spread {} above {}", summary.spread, value)),
+            Condition::Stale(exchange, max_us) => {
+                let last = inputs.last_update_us[exchange as usize].max(self.started_us);
+                let age = now.saturating_sub(last);
+                (age > max_us)
+                    .then(|| format!("{} no update for {:.1}s", exchange.name(), age as f64 / 1e6))
+            }
+            Condition::MidMove {
+                fraction,
+                window_us,
+            } => {
+                let mid = mid?;
+                let from = now.saturating_sub(window_us);
+                let (low, high) = self
+                    .mids
+                    .iter()
+                    .filter(|m| m.0 >= from)
+                    .fold((f64::MAX, f64::MIN), |(l, h), m| (l.min(m.1), h.max(m.1)));
+                let moved = (mid / low - 1.0).max(1.0 - mid / high);
+                (moved > fraction).then(|| {
+                    format!(
+                        "Mid {} moved {:.3}% within {}s",
+                        mid,
+                        moved * 100.0,
+                        window_us / 1_000_000
+                    )
+                })
+            }
+            Condition::Divergence { a, b, side, bps } => {
+                let top = |e: Exchange| {
+                    let book = &inputs.books[e as usize];
+                    let orders = match side {
+                        BookSide::Bid => &book.bids,
+                        BookSide::Ask => &book.asks,
+                    };
+                    orders.first().map(|o| o.price)
+                };
+                let (pa, pb) = (top(a)?, top(b)?);
+                let diff = (pa - pb).abs() / ((pa + pb) / 2.0) * 10_000.0;
+                (diff > bps).then(|| {
+                    format!(
+                        "{} {:?} {} and {} {} diverge {:.1} bps",
+                        a.name(),
+                        side,
+                        pa,
+                        b.name(),
+                        pb,
+                        diff
+                    )
+                })
+            }
+        }
+    }
+
+    fn actions(&self, rule: &str) -> &[AlertAction] {
+        self.rules
+            .iter()
+            .find(|r| r.rule.name == rule)
+            .map(|r| r.rule.actions.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+/// Evaluates alert rules against merged and per exchange books
+pub struct AlertEngine {
+    rules: RuleSet,
+    /// Rules can change without restart
+    config: watch::Receiver<ServerConfig>,
+    alerts: AlertsConfig,
+    summary: watch::Receiver<Summary>,
+    books: watch::Receiver<Vec<OrderBook>>,
+    registry: ExchangeRegistry,
+    http: reqwest::Client,
+}
+
+impl AlertEngine {
+    pub fn new(
+        mut config: watch::Receiver<ServerConfig>,
+        summary: watch::Receiver<Summary>,
+        books: watch::Receiver<Vec<OrderBook>>,
+        registry: ExchangeRegistry,
+    ) -> Result<Self, TrackerError> {
+        let alerts = config.borrow_and_update().alerts.clone();
+        Ok(Self {
+            rules: RuleSet::new(&alerts, now_us())?,
+            config,
+            alerts,
+            summary,
+            books,
+            registry,
+            http: reqwest::Client::new(),
+        })
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_millis(self.alerts.eval_interval_ms.max(1))
+    }
+
+    pub async fn run(mut self) {
+        let mut ticker = tokio::time::interval(self.interval());
+        let mut watching = true;
+        loop {
+            tokio::select! {
+                changed = self.config.changed(), if watching => {
+                    if changed.is_err() {
+                        // Reload disabled
+                        watching = false;
+                    } else {
+                        let alerts = self.config.borrow_and_update().alerts.clone();
+                        if self.configure(alerts) {
+                            ticker = tokio::time::interval(self.interval());
+                        }
+                    }
+                    continue;
+                }
+                _ = ticker.tick() => {}
+            }
+            if self.rules.rules.is_empty() {
+                continue;
+            }
+            let last_update_us: Vec<u64> = self
+                .registry
+                .exchanges()
+                .iter()
+                .map(|e| e.last_update_us)
+                .collect();
+            let fired = {
+                let summary = self.summary.borrow();
+                let books = self.books.borrow();
+                let inputs = Inputs {
+                    summary: &summary,
+                    books: &books,
+                    last_update_us: &last_update_us,
+                };
+                self.rules.evaluate(&inputs, now_us())
+            };
+            for alert in fired {
+                for action in self.rules.actions(&alert.rule) {
+                    self.dispatch(action, &alert);
+                }
+            }
+        }
+    }
+
+    /// Applies reloaded alert settings, `true` if they changed
+    fn configure(&mut self, alerts: AlertsConfig) -> bool {
+        if alerts == self.alerts {
+            return false;
+        }
+        self.rules = match self.rules.reconfigure(&alerts) {
+            Ok(next) => next,
+            Err(e) => {
+                error!(error = ?e, "Alert rules not reloaded");
+                return false;
+            }
+        };
+        info!(rules = self.rules.rules.len(), "Alert rules reloaded");
+        self.alerts = alerts;
+        true
+    }
+
+    fn dispatch(&self, action: &AlertAction, alert: &Alert) {
+        match action {
+            AlertAction::Log => {
+                warn!(rule = %alert.rule, message = %alert.message, "Alert");
+            }
+            AlertAction::Webhook { url } => {
+                let request = self
+                    .http
+                    .post(url)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(serde_json::to_string(alert).expect("Valid json"));
+                let rule = alert.rule.clone();
+                tokio::spawn(async move {
+                    match request.send().await.and_then(|r| r.error_for_status()) {
+                        Ok(_) => {}
+                        Err(e) => warn!(rule = %rule, error = %e, "Alert webhook failed"),
+                    }
+                });
+            }
+            AlertAction::Command { program, args } => {
+                let mut command = tokio::process::Command::new(program);
+                command
+                    .args(args)
+                    .env("ALERT_RULE", &alert.rule)
+                    .env("ALERT_MESSAGE", &alert.message)
+                    .env("ALERT_TIMESTAMP_US", alert.timestamp_us.to_string());
+                let rule = alert.rule.clone();
+                tokio::spawn(async move {
+                    match command.status().await {
+                        Ok(status) if status.success() => {}
+                        Ok(status) => warn!(rule = %rule, %status, "Alert command failed"),
+                        Err(e) => warn!(rule = %rule, error = %e, "Alert command failed"),
+                    }
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Inputs, RuleSet};
+    use crate::config::AlertsConfig;
+    use crate::server::{Level, Summary};
+    use crate::{Exchange, Order, OrderBook};
+
+    #[test]
+    fn test_rules() {
+        let cfg: AlertsConfig = serde_yaml::from_str(
+            r#"
+rules:
+  - name: crossed
+    condition: { type: spread_below, value: 0 }
+    for_ms: 500
+    cooldown_secs: 20
+  - name: bitstamp_stale
+    condition: { type: stale, exchange: bitstamp, secs: 5 }
+  - name: ask_divergence
+    condition: { type: divergence, exchanges: [binance, bitstamp], side: ask, bps: 20 }
+    actions: [{ type: webhook, url: "http://localhost/alert" }]
+"#,
+        )
+        .unwrap();
+        let mut rules = RuleSet::new(&cfg, 0).unwrap();
+
+        let level = |price| Level {
+            price,
+            raw_price: price,
+            amount: 1.0,
+            ..Default::default()
+        };
+        let crossed = Summary {
+            spread: -1.0,
+            bids: vec![level(101.0)],
+            asks: vec![level(100.0)],
+            ..Default::default()
+        };
+        let order = |price, exchange| Order {
+            price,
+            quantity: 1.0,
+            id: 0,
+            exchange,
+        };
+        let books = vec![
+            OrderBook {
+                asks: vec![order(100.0, Exchange::Binance)],
+                ..Default::default()
+            },
+            OrderBook {
+                exchange: Exchange::Bitstamp,
+                asks: vec![order(100.5, Exchange::Bitstamp)],
+                ..Default::default()
+            },
+        ];
+        let updates = [0, 4_000_000];
+        let inputs = Inputs {
+            summary: &crossed,
+            books: &books,
+            last_update_us: &updates,
+        };
+        let names = |alerts: Vec<super::Alert>| -> Vec<String> {
+            alerts.into_iter().map(|a| a.rule).collect()
+        };
+
+        // Divergence 50 bps fires at once, crossed book only after 500ms
+        assert_eq!(
+            names(rules.evaluate(&inputs, 1_000_000)),
+            ["ask_divergence"]
+        );
+        assert!(rules.evaluate(&inputs, 1_400_000).is_empty());
+        assert_eq!(names(rules.evaluate(&inputs, 1_500_000)), ["crossed"]);
+        // Deduplicated while active
+        assert!(rules.evaluate(&inputs, 2_000_000).is_empty());
+        assert_eq!(
+            names(rules.evaluate(&inputs, 9_500_000)),
+            ["bitstamp_stale"]
+        );
+        assert_eq!(rules.actions("ask_divergence").len(), 1);
+
+        // Resolved, then held back by cooldown
+        let normal = Summary::default();
+        let inputs = Inputs {
+            summary: &normal,
+            ..inputs
+        };
+        assert!(rules.evaluate(&inputs, 10_000_000).is_empty());
+        let inputs = Inputs {
+            summary: &crossed,
+            ..inputs
+        };
+        assert!(rules.evaluate(&inputs, 11_000_000).is_empty());
+        assert!(rules.evaluate(&inputs, 11_600_000).is_empty());
+        assert_eq!(names(rules.evaluate(&inputs, 21_500_000)), ["crossed"]);
+        // Wall clock stepped back
+        assert!(rules.evaluate(&inputs, 21_000_000).is_empty());
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+
+use strum::IntoEnumIterator;
+use tokio::sync::{broadcast, watch};
+use tracing::info;
+
+use crate::config::ArbitrageConfig;
+use crate::fees::FeeSchedule;
+use crate::latency::now_us;
+use crate::server::{opportunity::State, Level, Opportunity, Summary};
+use crate::Exchange;
+
+/// Watches merged summary for exchanges bidding above another exchange's ask.
+/// Executable amount is limited to the levels present in the merged summary.
+pub struct OpportunityDetector {
+    rx: watch::Receiver<Summary>,
+    tx: broadcast::Sender<Opportunity>,
+    /// Taker fees applied if set
+    fees: Option<FeeSchedule>,
+    min_profit: f64,
+    /// Open opportunities by buy and sell exchange id
+    open: HashMap<(i32, i32), Opportunity>,
+}
+
+impl OpportunityDetector {
+    pub fn new(
+        rx: watch::Receiver<Summary>,
+        tx: broadcast::Sender<Opportunity>,
+        fees: FeeSchedule,
+        cfg: &ArbitrageConfig,
+    ) -> Self {
+        Self {
+            rx,
+            tx,
+            fees: cfg.net_of_fees.then_some(fees),
+            min_profit: cfg.min_profit,
+            open: HashMap::new(),
+        }
+    }
+
+    pub async fn run(mut self) {
+        while self.rx.changed().await.is_ok() {
+            let found = find(&self.rx.borrow_and_update(), self.fees.as_ref());
+            self.update(found, now_us());
+        }
+        // Listener dropped - app is shutting down
+    }
+
+    /// Publishes opened, changed and closed opportunities
+    fn update(&mut self, found: Vec<Opportunity>, now: u64) {
+        let mut open = HashMap::with_capacity(found.len());
+        let min_profit = self.min_profit;
+        for mut o in found.into_iter().filter(|o| o.profit >= min_profit) {
+            let key = (o.buy_exchange, o.sell_exchange);
+            match self.open.remove(&key) {
+                Some(prev)
+                    if prev.amount == o.amount
+                        && prev.buy_price == o.buy_price
+                        && prev.sell_price == o.sell_price =>
+                {
+                    open.insert(key, prev);
+                    continue;
+                }
+                Some(prev) => {
+                    o.state = State::Update as i32;
+                    o.started_us = prev.started_us;
+                }
+                None => {
+                    info!(
+                        buy = o.buy_exchange,
+                        sell = o.sell_exchange,
+                        amount = o.amount,
+                        profit = o.profit,
+                        "Opportunity opened"
+                    );
+                    o.state = State::Open as i32;
+                    o.started_us = now;
+                }
+            }
+            o.updated_us = now;
+            o.duration_us = now.saturating_sub(o.started_us);
+            // No receivers is not an error
+            let _ = self.tx.send(o.clone());
+            open.insert(key, o);
+        }
+
+        for (_, mut o) in self.open.drain() {
+            o.state = State::Closed as i32;
+            o.updated_us = now;
+            o.duration_us = now.saturating_sub(o.started_us);
+            info!(
+                buy = o.buy_exchange,
+                sell = o.sell_exchange,
+                duration_us = o.duration_us,
+                "Opportunity closed"
+            );
+            let _ = self.tx.send(o);
+        }
+        self.open = open;
+    }
+}
+
+/// Profitable buy/sell exchange pairs in the summary
+fn find(summary: &Summary, fees: Option<&FeeSchedule>) -> Vec<Opportunity> {
+    // (price, amount) of one exchange side, using exchange quoted prices
+    let side = |levels: &[Level], exchange: Exchange, bid: bool| -> Vec<(f64, f64)> {
+        levels
+            .iter()
+            .filter(|l| l.exchange_id == exchange.id() as i32)
+            .map(|l| {
+                let price = fees
+                    .map(|f| f.taker_price(exchange, l.raw_price, bid))
+                    .unwrap_or(l.raw_price);
+                (price, l.amount)
+            })
+            .collect()
+    };
+
+    let mut found = vec![];
+    for buy in Exchange::iter() {
+        let asks = side(&summary.asks, buy, false);
+        for sell in Exchange::iter().filter(|&e| e != buy) {
+            let bids = side(&summary.bids, sell, true);
+            let (amount, cost, proceeds) = cross(&asks, &bids);
+            if amount > 0.0 {
+                found.push(Opportunity {
+                    buy_exchange: buy.id() as i32,
+                    sell_exchange: sell.id() as i32,
+                    buy_price: cost / amount,
+                    sell_price: proceeds / amount,
+                    amount,
+                    profit: proceeds - cost,
+                    net_of_fees: fees.is_some(),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+    found
+}
+
+/// Amount, cost and proceeds of buying `asks` and selling `bids` while ask < bid
+fn cross(asks: &[(f64, f64)], bids: &[(f64, f64)]) -> (f64, f64, f64) {
+    let (mut i, mut j) = (0, 0);
+    let mut ask_left = asks.first().map(|l| l.1).unwrap_or_default();
+    let mut bid_left = bids.first().map(|l| l.1).unwrap_or_default();
+    let (mut amount, mut cost, mut proceeds) = (0.0, 0.0, 0.0);
+    while i < asks.len() && j < bids.len() && asks[i].0 < bids[j].0 {
+        let q = ask_left.min(bid_left);
+        amount += q;
+        cost += q * asks[i].0;
+        proceeds += q * bids[j].0;
+        ask_left -= q;
+        bid_left -= q;
+        if ask_left <= 0.0 {
+            i += 1;
+            ask_left = asks.get(i).map(|l| l.1).unwrap_or_default();
+        }
+        if bid_left <= 0.0 {
+            j += 1;
+            bid_left = bids.get(j).map(|l| l.1).unwrap_or_default();
+        }
+    }
+    (amount, cost, proceeds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, OpportunityDetector};
+    use crate::config::ArbitrageConfig;
+    use crate::fees::FeeSchedule;
+    use crate::server::{opportunity::State, ExchangeId, Summary};
+    use crate::test_util::level;
+    use tokio::sync::{broadcast, watch};
+
+    #[test]
+    fn test_find() {
+        let summary = Summary {
+            bids: vec![
+                level(ExchangeId::Binance, 10.3, 1.0),
+                level(ExchangeId::Binance, 10.1, 2.0),
+                level(ExchangeId::Bitstamp, 9.9, 1.0),
+            ],
+            asks: vec![
+                level(ExchangeId::Bitstamp, 10.0, 1.5),
+                level(ExchangeId::Bitstamp, 10.2, 5.0),
+                level(ExchangeId::Binance, 10.4, 1.0),
+            ],
+            ..Default::default()
+        };
+
+        let found = find(&summary, None);
+        assert_eq!(found.len(), 1);
+        let o = &found[0];
+        assert_eq!(o.buy_exchange, ExchangeId::Bitstamp as i32);
+        assert_eq!(o.sell_exchange, ExchangeId::Binance as i32);
+        // 1.0 @ 10.0 -> 10.3, 0.5 @ 10.0 -> 10.1
+        assert!((o.amount - 1.5).abs() < 1e-9);
+        assert!((o.profit - 0.35).abs() < 1e-9);
+
+        let (tx, mut rx) = broadcast::channel(16);
+        let (_summary_tx, summary_rx) = watch::channel(Summary::default());
+        let cfg = ArbitrageConfig::default();
+        let fees = FeeSchedule { fees: vec![] };
+        let mut detector = OpportunityDetector::new(summary_rx, tx, fees, &cfg);
+
+        detector.update(found.clone(), 100);
+        detector.update(found, 200);
+        detector.update(vec![], 300);
+        let open = rx.try_recv().unwrap();
+        assert_eq!(open.state, State::Open as i32);
+        let closed = rx.try_recv().unwrap();
+        assert_eq!(closed.state, State::Closed as i32);
+        assert_eq!(closed.duration_us, 200);
+        assert!(rx.try_recv().is_err());
+    }
+}
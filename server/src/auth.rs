@@ -0,0 +1,250 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use tokio::sync::watch;
+use tonic::{service::Interceptor, Request, Status};
+use tracing::{debug, warn};
+
+use crate::config::{ApiKeyConfig, AuthConfig, ServerConfig};
+
+/// Permissions of an authenticated client
+#[derive(Debug)]
+pub struct ClientPermissions {
+    pub name: String,
+    /// Follows the live configuration, see `Authenticator::run`
+    instruments: RwLock<Vec<String>>,
+    max_depth: Option<u32>,
+    max_streams: Option<usize>,
+    active_streams: AtomicUsize,
+}
+
+impl From<&ApiKeyConfig> for ClientPermissions {
+    fn from(cfg: &ApiKeyConfig) -> Self {
+        Self {
+            name: cfg.name.clone(),
+            instruments: RwLock::new(cfg.instruments.clone()),
+            max_depth: cfg.max_depth,
+            max_streams: cfg.max_streams,
+            active_streams: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl ClientPermissions {
+    pub fn check_instrument(&self, instrument: &str) -> Result<(), Status> {
+        let instruments = self.instruments.read().expect("Permissions lock poisoned");
+        if instruments.is_empty() || instruments.iter().any(|i| i == instrument) {
+            Ok(())
+        } else {
+            Err(Status::permission_denied(format!(
+                "Instrument {} not allowed",
+                instrument
+            )))
+        }
+    }
+
+    /// Requested depth limited by key permissions
+    pub fn limit_depth(&self, depth: usize) -> usize {
+        match self.max_depth {
+            Some(max) => std::cmp::min(depth, max as usize),
+            None => depth,
+        }
+    }
+
+    /// Reserves a stream slot, released when returned guard is dropped
+    pub fn acquire_stream(self: &Arc<Self>) -> Result<StreamGuard, Status> {
+        let prev = self.active_streams.fetch_add(1, Ordering::SeqCst);
+        let guard = StreamGuard(self.clone());
+        if let Some(max) = self.max_streams {
+            if prev >= max {
+                return Err(Status::resource_exhausted(format!(
+                    "Maximum of {} concurrent streams reached",
+                    max
+                )));
+            }
+        }
+        Ok(guard)
+    }
+}
+
+/// Open stream slot of a client
+pub struct StreamGuard(Arc<ClientPermissions>);
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        self.0.active_streams.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// API keys with permissions
+type KeyTable = Vec<(String, Arc<ClientPermissions>)>;
+
+/// Checks API key from request metadata and attaches client permissions
+/// to request extensions. Passes every request through if auth is disabled.
+#[derive(Clone, Default)]
+pub struct Authenticator {
+    keys: Option<Arc<KeyTable>>,
+}
+
+impl Authenticator {
+    pub fn new(cfg: Option<&AuthConfig>) -> Self {
+        Self {
+            keys: cfg.map(|cfg| {
+                Arc::new(
+                    cfg.keys
+                        .iter()
+                        .map(|k| (k.key.clone(), Arc::new(ClientPermissions::from(k))))
+                        .collect(),
+                )
+            }),
+        }
+    }
+
+    /// Applies instruments of reloaded keys, other key settings need a restart
+    pub fn configure(&self, cfg: Option<&AuthConfig>) {
+        let (keys, cfg) = match (&self.keys, cfg) {
+            (Some(keys), Some(cfg)) => (keys, cfg),
+            _ => return,
+        };
+        for (key, permissions) in keys.iter() {
+            if let Some(k) = cfg.keys.iter().find(|k| k.key == *key) {
+                *permissions
+                    .instruments
+                    .write()
+                    .expect("Permissions lock poisoned") = k.instruments.clone();
+            }
+        }
+    }
+
+    /// Keeps key instruments in line with the live configuration
+    pub async fn run(self, mut config: watch::Receiver<ServerConfig>) {
+        while config.changed().await.is_ok() {
+            let auth = config.borrow_and_update().auth.clone();
+            self.configure(auth.as_ref());
+        }
+    }
+
+    /// Permissions of the key, `None` if auth is disabled
+    pub fn authenticate(
+        &self,
+        token: Option<&str>,
+    ) -> Result<Option<Arc<ClientPermissions>>, Status> {
+        let keys = match &self.keys {
+            Some(keys) => keys,
+            None => return Ok(None),
+        };
+
+        let token = token.ok_or_else(|| Status::unauthenticated("Missing API key"))?;
+        keys.iter()
+            .find(|(key, _)| constant_time_eq(key.as_bytes(), token.as_bytes()))
+            .map(|(_, p)| {
+                debug!(client = %p.name, "Client authenticated");
+                Some(p.clone())
+            })
+            .ok_or_else(|| Status::unauthenticated("Invalid API key"))
+    }
+
+    fn token<T>(req: &Request<T>) -> Option<&str> {
+        let meta = req.metadata();
+        meta.get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(bearer_token)
+            .or_else(|| meta.get("x-api-key").and_then(|v| v.to_str().ok()))
+    }
+}
+
+impl Interceptor for Authenticator {
+    fn call(&mut self, mut req: Request<()>) -> Result<Request<()>, Status> {
+        match self.authenticate(Self::token(&req)) {
+            Ok(permissions) => {
+                if let Some(p) = permissions {
+                    req.extensions_mut().insert(p);
+                }
+                Ok(req)
+            }
+            Err(e) => {
+                warn!(remote = ?req.remote_addr(), error = %e.message(), "Authentication failed");
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Key of an `authorization` value with the `Bearer` scheme in any case
+pub fn bearer_token(value: &str) -> Option<&str> {
+    let (scheme, token) = value.trim().split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("bearer") {
+        Some(token.trim())
+    } else {
+        None
+    }
+}
+
+/// Comparison time independent of the position of the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Authenticator;
+    use crate::config::{ApiKeyConfig, AuthConfig};
+    use std::sync::Arc;
+    use tonic::service::Interceptor;
+
+    fn request(header: &'static str, value: &str) -> tonic::Request<()> {
+        let mut req = tonic::Request::new(());
+        req.metadata_mut().insert(header, value.parse().unwrap());
+        req
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let cfg = AuthConfig {
+            keys: vec![ApiKeyConfig {
+                name: "partner".into(),
+                key: "secret".into(),
+                instruments: vec!["BTCUSDC".into()],
+                max_depth: Some(5),
+                max_streams: Some(1),
+            }],
+        };
+        let mut auth = Authenticator::new(Some(&cfg));
+
+        assert!(auth.call(tonic::Request::new(())).is_err());
+        assert!(auth.call(request("x-api-key", "wrong")).is_err());
+        assert!(auth.call(request("x-api-key", "secret")).is_ok());
+
+        let req = auth
+            .call(request("authorization", "Bearer secret"))
+            .unwrap();
+        let p = req
+            .extensions()
+            .get::<Arc<super::ClientPermissions>>()
+            .unwrap();
+        assert!(p.check_instrument("BTCUSDC").is_ok());
+        assert!(p.check_instrument("ETHUSDC").is_err());
+        assert_eq!(p.limit_depth(10), 5);
+
+        let mut reloaded = cfg.clone();
+        reloaded.keys[0].instruments = vec!["ETHUSDC".into()];
+        auth.configure(Some(&reloaded));
+        assert!(p.check_instrument("BTCUSDC").is_err());
+        assert!(p.check_instrument("ETHUSDC").is_ok());
+
+        assert!(auth.call(request("authorization", "bearer secret")).is_ok());
+        // Other schemes fall through to x-api-key
+        let mut req = request("authorization", "Basic dXNlcg==");
+        req.metadata_mut()
+            .insert("x-api-key", "secret".parse().unwrap());
+        assert!(auth.call(req).is_ok());
+
+        let guard = p.acquire_stream().unwrap();
+        assert!(p.acquire_stream().is_err());
+        drop(guard);
+        assert!(p.acquire_stream().is_ok());
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use tokio::sync::{broadcast, watch};
+
+use crate::config::BarsConfig;
+use crate::latency::now_us;
+use crate::server::{Bar, Ohlc, Summary};
+
+/// Bars are closed at most this late when no book updates arrive
+const CLOSE_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Best prices of the merged book, exchange quoted
+#[derive(Debug, Clone, Copy)]
+struct Values {
+    bid: f64,
+    ask: f64,
+}
+
+impl Values {
+    fn from_summary(summary: &Summary) -> Option<Self> {
+        let bid = summary.bids.iter().map(|l| l.raw_price).reduce(f64::max)?;
+        let ask = summary.asks.iter().map(|l| l.raw_price).reduce(f64::min)?;
+        Some(Self { bid, ask })
+    }
+
+    fn mid(&self) -> f64 {
+        (self.bid + self.ask) / 2.0
+    }
+
+    fn spread(&self) -> f64 {
+        self.ask - self.bid
+    }
+}
+
+fn ohlc(v: f64) -> Option<Ohlc> {
+    Some(Ohlc {
+        open: v,
+        high: v,
+        low: v,
+        close: v,
+    })
+}
+
+fn ohlc_update(o: &mut Option<Ohlc>, v: f64) {
+    if let Some(o) = o.as_mut() {
+        o.high = o.high.max(v);
+        o.low = o.low.min(v);
+        o.close = v;
+    }
+}
+
+/// Bars of one interval
+struct Series {
+    interval_us: u64,
+    bar: Option<Bar>,
+    /// Start of the time covered by spread integral
+    since_us: u64,
+    /// Last spread change
+    last_us: u64,
+    /// Spread integrated over time
+    spread_sum: f64,
+}
+
+impl Series {
+    fn new(secs: u32) -> Self {
+        Self {
+            interval_us: secs as u64 * 1_000_000,
+            bar: None,
+            since_us: 0,
+            last_us: 0,
+            spread_sum: 0.0,
+        }
+    }
+
+    fn open(&mut self, start: u64, at: u64, v: Values, updates: u64) {
+        self.bar = Some(Bar {
+            interval_secs: (self.interval_us / 1_000_000) as u32,
+            start_us: start,
+            end_us: start + self.interval_us,
+            mid: ohlc(v.mid()),
+            bid: ohlc(v.bid),
+            ask: ohlc(v.ask),
+            spread: ohlc(v.spread()),
+            twa_spread: v.spread(),
+            updates,
+        });
+        self.since_us = at;
+        self.last_us = at;
+        self.spread_sum = 0.0;
+    }
+
+    /// Closes bars ended before `now`, next bar opens with the last book state
+    fn roll(&mut self, now: u64, closed: &mut Vec<Bar>) {
+        while let Some(bar) = self.bar.as_mut() {
+            if now < bar.end_us {
+                break;
+            }
+            let spread = bar.spread.as_ref().map(|s| s.close).unwrap_or_default();
+            self.spread_sum += spread * (bar.end_us - self.last_us) as f64;
+            let covered = bar.end_us - self.since_us;
+            if covered > 0 {
+                bar.twa_spread = self.spread_sum / covered as f64;
+            }
+            closed.push(bar.clone());
+
+            let end = bar.end_us;
+            let last = bar.bid.as_ref().zip(bar.ask.as_ref()).map(|(b, a)| Values {
+                bid: b.close,
+                ask: a.close,
+            });
+            match last {
+                Some(v) => self.open(end, end, v, 0),
+                None => self.bar = None,
+            }
+        }
+    }
+
+    fn update(&mut self, now: u64, v: Values) {
+        match self.bar.as_mut() {
+            Some(bar) => {
+                let spread = bar.spread.as_ref().map(|s| s.close).unwrap_or_default();
+                self.spread_sum += spread * now.saturating_sub(self.last_us) as f64;
+                self.last_us = now;
+                ohlc_update(&mut bar.mid, v.mid());
+                ohlc_update(&mut bar.bid, v.bid);
+                ohlc_update(&mut bar.ask, v.ask);
+                ohlc_update(&mut bar.spread, v.spread());
+                bar.updates += 1;
+            }
+            None => self.open(now - now % self.interval_us, now, v, 1),
+        }
+    }
+}
+
+/// Closed bars history and live feed, shared with the gRPC service
+#[derive(Clone)]
+pub struct BarStore {
+    intervals: Vec<u32>,
+    history: Arc<RwLock<Vec<VecDeque<Bar>>>>,
+    capacity: usize,
+    tx: broadcast::Sender<Bar>,
+}
+
+impl BarStore {
+    pub fn intervals(&self) -> &[u32] {
+        &self.intervals
+    }
+
+    /// Most recent closed bars of the interval, oldest first, all kept if `limit` is 0
+    pub fn history(&self, interval_secs: u32, limit: usize) -> Option<Vec<Bar>> {
+        let idx = self.intervals.iter().position(|&i| i == interval_secs)?;
+        let history = self.history.read().expect("Bars lock poisoned");
+        let bars = &history[idx];
+        let skip = match limit {
+            0 => 0,
+            l => bars.len().saturating_sub(l),
+        };
+        Some(bars.iter().skip(skip).cloned().collect())
+    }
+
+    /// Bars of all intervals as they close
+    pub fn subscribe(&self) -> broadcast::Receiver<Bar> {
+        self.tx.subscribe()
+    }
+
+    fn push(&self, bar: Bar) {
+        if let Some(idx) = self.intervals.iter().position(|&i| i == bar.interval_secs) {
+            let mut history = self.history.write().expect("Bars lock poisoned");
+            let bars = &mut history[idx];
+            if bars.len() == self.capacity {
+                bars.pop_front();
+            }
+            bars.push_back(bar.clone());
+        }
+        // No receivers is not an error
+        let _ = self.tx.send(bar);
+    }
+}
+
+/// Builds time bars of mid, best bid, best ask and spread from the merged book
+pub struct BarBuilder {
+    rx: watch::Receiver<Summary>,
+    series: Vec<Series>,
+    store: BarStore,
+}
+
+impl BarBuilder {
+    pub fn new(cfg: &BarsConfig, rx: watch::Receiver<Summary>) -> Self {
+        let intervals: Vec<u32> = cfg
+            .intervals_secs
+            .iter()
+            .copied()
+            .filter(|&i| i > 0)
+            .collect();
+        Self {
+            rx,
+            series: intervals.iter().map(|&i| Series::new(i)).collect(),
+            store: BarStore {
+                history: Arc::new(RwLock::new(vec![VecDeque::new(); intervals.len()])),
+                intervals,
+                capacity: cfg.history.max(1),
+                tx: broadcast::channel(1024).0,
+            },
+        }
+    }
+
+    pub fn store(&self) -> BarStore {
+        self.store.clone()
+    }
+
+    pub async fn run(mut self) {
+        let mut ticker = tokio::time::interval(CLOSE_CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                changed = self.rx.changed() => {
+                    if changed.is_err() {
+                        // Listener dropped - app is shutting down
+                        break;
+                    }
+                    let values = Values::from_summary(&self.rx.borrow_and_update());
+                    if let Some(v) = values {
+                        self.update(now_us(), Some(v));
+                    }
+                }
+                _ = ticker.tick() => self.update(now_us(), None),
+            }
+        }
+    }
+
+    fn update(&mut self, now: u64, values: Option<Values>) {
+        let mut closed = vec![];
+        for s in self.series.iter_mut() {
+            s.roll(now, &mut closed);
+            if let Some(v) = values {
+                s.update(now, v);
+            }
+        }
+        for bar in closed {
+            self.store.push(bar);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BarBuilder, Values};
+    use crate::config::BarsConfig;
+    use crate::server::Summary;
+    use tokio::sync::watch;
+
+    #[test]
+    fn test_bars() {
+        let cfg = BarsConfig {
+            intervals_secs: vec![1],
+            history: 2,
+        };
+        let (_tx, rx) = watch::channel(Summary::default());
+        let mut builder = BarBuilder::new(&cfg, rx);
+        let store = builder.store();
+        let v = |bid, ask| Some(Values { bid, ask });
+
+        builder.update(10_000_000, v(99.0, 101.0));
+        builder.update(10_500_000, v(100.0, 101.0));
+        builder.update(10_750_000, v(98.0, 102.0));
+        assert!(store.history(1, 0).unwrap().is_empty());
+
+        // Quiet second produces a flat bar
+        builder.update(12_100_000, None);
+        let bars = store.history(1, 0).unwrap();
+        assert_eq!(bars.len(), 2);
+        let bar = &bars[0];
+        assert_eq!(bar.start_us, 10_000_000);
+        assert_eq!(bar.updates, 3);
+        let mid = bar.mid.as_ref().unwrap();
+        assert_eq!(
+            (mid.open, mid.high, mid.low, mid.close),
+            (100.0, 100.5, 100.0, 100.0)
+        );
+        assert_eq!(bar.bid.as_ref().unwrap().low, 98.0);
+        // 2.0 for 0.5s, 1.0 for 0.25s, 4.0 for 0.25s
+        assert!((bar.twa_spread - 2.25).abs() < 1e-9);
+        assert_eq!(bars[1].updates, 0);
+        assert_eq!(bars[1].twa_spread, 4.0);
+
+        assert_eq!(store.history(1, 1).unwrap()[0].start_us, 11_000_000);
+        assert!(store.history(60, 0).is_none());
+    }
+}
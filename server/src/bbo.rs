@@ -0,0 +1,147 @@
+use strum::EnumCount;
+use tokio::sync::{mpsc, watch};
+use tracing::trace;
+
+use crate::server::{Bbo, VenueBbo};
+use crate::Exchange;
+
+/// Keeps the latest top of book of each exchange from real-time BBO feeds,
+/// published on every change without waiting for depth frames
+pub struct BboMerger {
+    rx: mpsc::UnboundedReceiver<crate::Bbo>,
+    tx: watch::Sender<Vec<VenueBbo>>,
+    /// Latest top of book, indexed by `Exchange`
+    venues: Vec<Option<VenueBbo>>,
+}
+
+impl BboMerger {
+    pub fn new(rx: mpsc::UnboundedReceiver<crate::Bbo>) -> Self {
+        Self {
+            rx,
+            tx: watch::channel(vec![]).0,
+            venues: vec![None; Exchange::COUNT],
+        }
+    }
+
+    /// Top of book of exchanges with BBO feeds, in `Exchange` order
+    pub fn subscribe(&self) -> watch::Receiver<Vec<VenueBbo>> {
+        self.tx.subscribe()
+    }
+
+    pub async fn run(mut self) {
+        while let Some(bbo) = self.rx.recv().await {
+            self.update(bbo);
+        }
+        // Connectors dropped - app is shutting down
+    }
+
+    fn update(&mut self, bbo: crate::Bbo) {
+        trace!(exchange = ?bbo.exchange, "Received BBO");
+        let venue = VenueBbo {
+            exchange_id: bbo.exchange.id() as i32,
+            bid_price: bbo.bid_price,
+            bid_quantity: bbo.bid_quantity,
+            ask_price: bbo.ask_price,
+            ask_quantity: bbo.ask_quantity,
+            event_time_us: bbo.timestamps.event.unwrap_or_default(),
+            received_us: bbo.timestamps.received,
+        };
+        // Cleared by a disconnected or stopped connector
+        let venue = Some(venue).filter(|v| v.bid_quantity > 0.0 || v.ask_quantity > 0.0);
+        let quote = |v: &VenueBbo| (v.bid_price, v.bid_quantity, v.ask_price, v.ask_quantity);
+        let slot = &mut self.venues[bbo.exchange as usize];
+        let changed = slot.as_ref().map(quote) != venue.as_ref().map(quote);
+        *slot = venue;
+        if changed {
+            self.tx
+                .send_replace(self.venues.iter().flatten().cloned().collect());
+        }
+    }
+}
+
+/// Best bid and offer across given venues, `None` unless both sides are quoted
+pub fn merge(venues: Vec<VenueBbo>) -> Option<Bbo> {
+    let bid = venues
+        .iter()
+        .filter(|v| v.bid_quantity > 0.0)
+        // First venue wins ties
+        .min_by(|a, b| b.bid_price.total_cmp(&a.bid_price))?;
+    let ask = venues
+        .iter()
+        .filter(|v| v.ask_quantity > 0.0)
+        .min_by(|a, b| a.ask_price.total_cmp(&b.ask_price))?;
+    // Quantity of all venues quoting the best price
+    let bid_quantity = venues
+        .iter()
+        .filter(|v| v.bid_price == bid.bid_price)
+        .map(|v| v.bid_quantity)
+        .sum();
+    let ask_quantity = venues
+        .iter()
+        .filter(|v| v.ask_price == ask.ask_price)
+        .map(|v| v.ask_quantity)
+        .sum();
+    Some(Bbo {
+        bid_price: bid.bid_price,
+        bid_quantity,
+        bid_exchange_id: bid.exchange_id,
+        ask_price: ask.ask_price,
+        ask_quantity,
+        ask_exchange_id: ask.exchange_id,
+        spread: ask.ask_price - bid.bid_price,
+        venues,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge, BboMerger};
+    use crate::binance::api::StreamMsg;
+    use crate::server::ExchangeId;
+    use crate::{Bbo, Exchange};
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_merge() {
+        let msg: StreamMsg = serde_json::from_str(
+            r#"{"stream":"btcusdt@bookTicker","data":{"u":400900217,"s":"BTCUSDT",
+            "b":"100.0","B":"1.5","a":"101.0","A":"2.0"}}"#,
+        )
+        .unwrap();
+        let binance = match msg.data {
+            crate::binance::api::StreamData::BookTicker(t) => Bbo::from(t),
+            _ => panic!("Book ticker expected"),
+        };
+        let bitstamp = Bbo {
+            exchange: Exchange::Bitstamp,
+            bid_price: 100.0,
+            bid_quantity: 0.5,
+            ask_price: 100.5,
+            ask_quantity: 1.0,
+            timestamps: Default::default(),
+        };
+
+        let (_tx, rx) = mpsc::unbounded_channel();
+        let mut merger = BboMerger::new(rx);
+        let mut venues = merger.subscribe();
+        merger.update(binance);
+        assert!(venues.has_changed().unwrap());
+        assert!(merge(venues.borrow_and_update().clone()).is_some());
+        merger.update(binance);
+        assert!(!venues.has_changed().unwrap());
+        merger.update(bitstamp);
+
+        let bbo = merge(venues.borrow_and_update().clone()).unwrap();
+        assert_eq!(bbo.bid_price, 100.0);
+        assert_eq!(bbo.bid_quantity, 2.0);
+        assert_eq!(bbo.bid_exchange_id, ExchangeId::Binance as i32);
+        assert_eq!(bbo.ask_exchange_id, ExchangeId::Bitstamp as i32);
+        assert_eq!(bbo.spread, 0.5);
+        assert_eq!(bbo.venues.len(), 2);
+
+        // Disconnected exchange leaves the merge
+        merger.update(Bbo::cleared(Exchange::Bitstamp));
+        assert!(venues.has_changed().unwrap());
+        assert_eq!(venues.borrow().len(), 1);
+    }
+}
@@ -17,6 +17,54 @@ pub struct OrderBook {
     pub asks: Vec<Order>,
 }
 
+/// Trade or aggregated trade event
+#[derive(Deserialize, Debug, Clone)]
+pub struct Trade {
+    #[serde(rename = "t", alias = "a")]
+    pub id: u64,
+    #[serde(rename = "p", deserialize_with = "crate::de_float")]
+    pub price: f64,
+    #[serde(rename = "q", deserialize_with = "crate::de_float")]
+    pub quantity: f64,
+    /// Trade time, milliseconds since UNIX epoch
+    #[serde(rename = "T")]
+    pub time: u64,
+    /// Buyer was the maker, so the taker sold
+    #[serde(rename = "m")]
+    pub buyer_maker: bool,
+}
+
+/// Real-time best bid and offer
+#[derive(Deserialize, Debug, Clone)]
+pub struct BookTicker {
+    #[serde(rename = "u")]
+    pub update_id: u64,
+    #[serde(rename = "b", deserialize_with = "crate::de_float")]
+    pub bid_price: f64,
+    #[serde(rename = "B", deserialize_with = "crate::de_float")]
+    pub bid_quantity: f64,
+    #[serde(rename = "a", deserialize_with = "crate::de_float")]
+    pub ask_price: f64,
+    #[serde(rename = "A", deserialize_with = "crate::de_float")]
+    pub ask_quantity: f64,
+}
+
+/// Combined stream payload
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum StreamData {
+    Trade(Trade),
+    BookTicker(BookTicker),
+    OrderBook(OrderBook),
+}
+
+/// Combined stream message
+#[derive(Deserialize, Debug, Clone)]
+pub struct StreamMsg {
+    pub stream: String,
+    pub data: StreamData,
+}
+
 impl OrderBook {
     pub fn changed(&self, other: &Self) -> bool {
         self.bids != other.bids || self.asks != other.asks
@@ -26,6 +74,60 @@ impl OrderBook {
 #[derive(Deserialize, Debug, Clone)]
 pub struct SymbolInfo {
     pub symbol: String,
+    #[serde(default)]
+    pub baseAsset: String,
+    #[serde(default)]
+    pub quoteAsset: String,
+    #[serde(default)]
+    pub filters: Vec<SymbolFilter>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "filterType", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SymbolFilter {
+    PriceFilter {
+        #[serde(deserialize_with = "crate::de_float")]
+        tickSize: f64,
+    },
+    LotSize {
+        #[serde(deserialize_with = "crate::de_float")]
+        stepSize: f64,
+        #[serde(deserialize_with = "crate::de_float")]
+        minQty: f64,
+    },
+    MinNotional {
+        #[serde(deserialize_with = "crate::de_float")]
+        minNotional: f64,
+    },
+    Notional {
+        #[serde(deserialize_with = "crate::de_float")]
+        minNotional: f64,
+    },
+    #[serde(other)]
+    Other,
+}
+
+impl From<&SymbolInfo> for crate::registry::SymbolRules {
+    fn from(info: &SymbolInfo) -> Self {
+        let mut rules = Self {
+            base: info.baseAsset.clone(),
+            quote: info.quoteAsset.clone(),
+            ..Default::default()
+        };
+        for filter in &info.filters {
+            match filter {
+                SymbolFilter::PriceFilter { tickSize } => rules.tick_size = *tickSize,
+                SymbolFilter::LotSize { stepSize, minQty } => {
+                    rules.lot_size = *stepSize;
+                    rules.min_quantity = *minQty;
+                }
+                SymbolFilter::MinNotional { minNotional }
+                | SymbolFilter::Notional { minNotional } => rules.min_notional = *minNotional,
+                SymbolFilter::Other => {}
+            }
+        }
+        rules
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -2,8 +2,11 @@ use futures_util::stream::{SplitSink, SplitStream};
 use futures_util::{SinkExt, StreamExt};
 use tokio::sync::mpsc;
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, WebSocketStream};
+use tracing::{debug, info, info_span, trace, warn, Instrument};
 
-use crate::{config::BinanceConfig, TrackerError};
+use crate::registry::{ExchangeRegistry, SymbolRules};
+use crate::server as proto;
+use crate::{config::BinanceConfig, latency::now_us, Exchange, TrackerError};
 
 use self::api::InfoResponse;
 
@@ -14,10 +17,24 @@ type WsSink =
 type WsStream =
     SplitStream<WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>>;
 
-const INFO_ENDPOINT: &str = "https://api.binance.com/api/v3/exchangeInfo";
-const DEPTH_ENDPOINT_PREFIX: &str = "wss://stream.binance.com:9443/ws/";
-const DEPTH_ENDPOINT_SUFFIX: &str = "@depth10@100ms";
+const INFO_PATH: &str = "/api/v3/exchangeInfo";
+/// Combined streams path, stream names are joined with `/`
+pub(crate) const STREAM_PATH: &str = "/stream?streams=";
+const DEPTH_ENDPOINT_SUFFIX: &str = "@100ms";
+/// Partial book depth stream levels supported by Binance
+pub(crate) const DEPTH_LEVELS: [u32; 3] = [5, 10, 20];
 const EX_NAME: &str = "Binance";
+const EXCHANGE: Exchange = Exchange::Binance;
+
+/// Combined streams URL of a WebSocket base URL
+pub(crate) fn stream_url(base: &str, streams: &[String]) -> String {
+    format!(
+        "{}{}{}",
+        base.trim_end_matches('/'),
+        STREAM_PATH,
+        streams.join("/")
+    )
+}
 
 enum ConnectionStatus {
     Disconnected,
@@ -28,32 +45,64 @@ pub struct BinanceSubscriber {
     cfg: BinanceConfig,
     status: ConnectionStatus,
     tx: mpsc::UnboundedSender<crate::OrderBook>,
+    /// Trade stream subscribed if set
+    trades: Option<mpsc::UnboundedSender<crate::Trade>>,
+    /// Book ticker subscribed if set
+    bbo: Option<mpsc::UnboundedSender<crate::Bbo>>,
+    registry: ExchangeRegistry,
     ws: Option<(WsSink, WsStream)>,
     last_book: Option<api::OrderBook>,
+    attempt: u64,
 }
 
 impl BinanceSubscriber {
     pub fn new(
         cfg: BinanceConfig,
         tx: mpsc::UnboundedSender<crate::OrderBook>,
+        trades: Option<mpsc::UnboundedSender<crate::Trade>>,
+        bbo: Option<mpsc::UnboundedSender<crate::Bbo>>,
+        registry: ExchangeRegistry,
     ) -> Result<Self, String> {
+        if !DEPTH_LEVELS.contains(&cfg.depth) {
+            return Err(format!(
+                "{}: unsupported depth {}, expected one of {:?}",
+                EX_NAME, cfg.depth, DEPTH_LEVELS
+            ));
+        }
+        let bbo = bbo.filter(|_| cfg.bbo);
         Ok(Self {
             cfg,
             status: ConnectionStatus::Disconnected,
             tx,
+            trades,
+            bbo,
+            registry,
             ws: None,
             last_book: None,
+            attempt: 0,
         })
     }
 
     pub async fn run(&mut self) -> Result<(), TrackerError> {
+        let span = info_span!("exchange", exchange = EX_NAME, symbol = %self.cfg.symbol);
+        self.run_loop().instrument(span).await
+    }
+
+    async fn run_loop(&mut self) -> Result<(), TrackerError> {
         loop {
             if let Err(e) = self.process().await {
                 if let TrackerError::Cnnection(_e) = &e {
-                    eprintln!("{}: {:?}", EX_NAME, e);
+                    warn!(attempt = self.attempt, error = ?e, "Connection lost, retrying in 2s");
                     self.status = ConnectionStatus::Disconnected;
+                    self.registry
+                        .set_connection(EXCHANGE, proto::ConnectionState::Disconnected);
+                    // Book and top of book are unknown until reconnected
+                    self.last_book = None;
+                    let _ = self.tx.send(crate::OrderBook::cleared(EXCHANGE));
+                    if let Some(bbo) = &self.bbo {
+                        let _ = bbo.send(crate::Bbo::cleared(EXCHANGE));
+                    }
                     tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-                    println!("{}: Retrying.", EX_NAME);
                 } else {
                     return Err(e);
                 }
@@ -64,9 +113,15 @@ impl BinanceSubscriber {
     async fn process(&mut self) -> Result<(), TrackerError> {
         match &mut self.status {
             ConnectionStatus::Disconnected => {
+                self.attempt += 1;
+                self.registry
+                    .set_connection(EXCHANGE, proto::ConnectionState::Connecting);
                 self.check_config().await?;
                 self.connect().await?;
                 self.status = ConnectionStatus::Updating;
+                self.attempt = 0;
+                self.registry
+                    .set_connection(EXCHANGE, proto::ConnectionState::Connected);
             }
             ConnectionStatus::Updating => {
                 self.rcv_update().await?;
@@ -76,19 +131,25 @@ impl BinanceSubscriber {
     }
 
     async fn connect(&mut self) -> Result<(), TrackerError> {
-        println!("{}: Connecting", EX_NAME);
-        let (ws_stream, _) = connect_async(&format!(
-            "{}{}{}",
-            DEPTH_ENDPOINT_PREFIX,
-            self.cfg.symbol.to_lowercase(),
-            DEPTH_ENDPOINT_SUFFIX
-        ))
-        .await
-        .map_err(|e| TrackerError::Cnnection(format!("Ws Connection error {}", e)))?;
-
-        println!(
-            "{}: WebSocket handshake has been successfully completed",
-            EX_NAME
+        info!(attempt = self.attempt, "Connecting");
+        let symbol = self.cfg.symbol.to_lowercase();
+        let mut streams = vec![format!(
+            "{}@depth{}{}",
+            symbol, self.cfg.depth, DEPTH_ENDPOINT_SUFFIX
+        )];
+        if self.trades.is_some() {
+            streams.push(format!("{}@{}", symbol, self.cfg.trade_stream.name()));
+        }
+        if self.bbo.is_some() {
+            streams.push(format!("{}@bookTicker", symbol));
+        }
+        let (ws_stream, _) = connect_async(&stream_url(self.cfg.ws_url(), &streams))
+            .await
+            .map_err(|e| TrackerError::Cnnection(format!("Ws Connection error {}", e)))?;
+
+        info!(
+            attempt = self.attempt,
+            "WebSocket handshake has been successfully completed"
         );
 
         self.ws = Some(ws_stream.split());
@@ -97,7 +158,8 @@ impl BinanceSubscriber {
     }
 
     async fn check_config(&self) -> Result<(), TrackerError> {
-        let resp = reqwest::get(INFO_ENDPOINT)
+        let url = format!("{}{}", self.cfg.rest_url().trim_end_matches('/'), INFO_PATH);
+        let resp = reqwest::get(&url)
             .await
             .map_err(|e| TrackerError::Other(format!("Get info error: {}", e)))?;
         let txt = resp
@@ -106,12 +168,15 @@ impl BinanceSubscriber {
             .map_err(|e| TrackerError::Other(format!("{}: {}", EX_NAME, e)))?;
         let info: InfoResponse = serde_json::from_str(&txt)
             .map_err(|e| TrackerError::Other(format!("Info response parse error: {}", e)))?;
-        let symbols: Vec<String> = info.symbols.into_iter().map(|s| s.symbol).collect();
-        if !symbols.contains(&self.cfg.symbol) {
-            return Err(TrackerError::Config(format!(
-                "{}: Invalid symbol {} Valid symbols are:\n{:?}",
-                EX_NAME, self.cfg.symbol, symbols
-            )));
+        match info.symbols.iter().find(|s| s.symbol == self.cfg.symbol) {
+            Some(symbol) => self.registry.set_rules(EXCHANGE, SymbolRules::from(symbol)),
+            None => {
+                let symbols: Vec<String> = info.symbols.into_iter().map(|s| s.symbol).collect();
+                return Err(TrackerError::Config(format!(
+                    "{}: Invalid symbol {} Valid symbols are:\n{:?}",
+                    EX_NAME, self.cfg.symbol, symbols
+                )));
+            }
         }
 
         Ok(())
@@ -125,14 +190,12 @@ impl BinanceSubscriber {
             .1
             .next()
             .await
-            .ok_or_else( || TrackerError::Cnnection(format!(
-                "{}: Ws stream terminated",
-                EX_NAME
-            )))?
+            .ok_or_else(|| TrackerError::Cnnection(format!("{}: Ws stream terminated", EX_NAME)))?
             .map_err(|e| TrackerError::Cnnection(format!("{}: Rcv error {}", EX_NAME, e)))?;
+        let received = now_us();
 
         if msg.is_ping() {
-            println!("{}: Ping", EX_NAME);
+            debug!("Ping");
             let data = msg.into_data();
             self.ws
                 .as_mut()
@@ -142,7 +205,7 @@ impl BinanceSubscriber {
                 .await
                 .map_err(|e| TrackerError::Cnnection(format!("{}: Send error {}", EX_NAME, e)))?;
 
-            println!("{}: Pong", EX_NAME);
+            debug!("Pong");
 
             return Ok(());
         }
@@ -151,23 +214,56 @@ impl BinanceSubscriber {
             .into_text()
             .map_err(|e| TrackerError::Other(format!("{}: Msg to text error {}", EX_NAME, e)))?;
 
-        let book: api::OrderBook = serde_json::from_str(&text).map_err(|e| {
+        let msg: api::StreamMsg = serde_json::from_str(&text).map_err(|e| {
             TrackerError::Other(format!(
                 "{}: Update msg parse error: {}\n{}",
                 EX_NAME, e, text
             ))
         })?;
+        let parsed = now_us();
 
-        if let Some(u) = &self.last_book {
-            if book.changed(u) {
-                self.tx.send(book.clone().into()).map_err(|e| {
-                    TrackerError::Other(format!("{}: Book send error: {}", EX_NAME, e))
-                })?;
-                self.last_book = Some(book);
+        let book = match msg.data {
+            api::StreamData::OrderBook(book) => book,
+            api::StreamData::Trade(trade) => {
+                trace!(id = trade.id, "Trade");
+                self.registry.touch(EXCHANGE);
+                if let Some(tx) = &self.trades {
+                    let trade = crate::Trade::from(trade).with_local_timestamps(received, parsed);
+                    tx.send(trade).map_err(|e| {
+                        TrackerError::Other(format!("{}: Trade send error: {}", EX_NAME, e))
+                    })?;
+                }
+                return Ok(());
+            }
+            api::StreamData::BookTicker(ticker) => {
+                trace!(update_id = ticker.update_id, "Book ticker");
+                self.registry.touch(EXCHANGE);
+                if let Some(tx) = &self.bbo {
+                    let mut bbo = crate::Bbo::from(ticker);
+                    bbo.timestamps.received = received;
+                    bbo.timestamps.parsed = parsed;
+                    tx.send(bbo).map_err(|e| {
+                        TrackerError::Other(format!("{}: Bbo send error: {}", EX_NAME, e))
+                    })?;
+                }
+                return Ok(());
             }
-        } else {
+        };
+
+        trace!(last_update_id = book.lastUpdateId, "Book update");
+
+        let changed = match &self.last_book {
+            Some(u) => book.changed(u),
+            None => true,
+        };
+
+        self.registry.touch_book(EXCHANGE);
+
+        if changed {
+            let update =
+                crate::OrderBook::from(book.clone()).with_local_timestamps(received, parsed);
             self.tx
-                .send(book.clone().into())
+                .send(update)
                 .map_err(|e| TrackerError::Other(format!("{}: Book send error: {}", EX_NAME, e)))?;
             self.last_book = Some(book);
         }
@@ -12,19 +12,54 @@ pub struct SubscribeData {
 }
 
 impl SubscribeRequest {
-    pub fn new(symbol: &str) -> Self {
+    pub fn new(channel: String) -> Self {
         Self {
             event: "bts:subscribe".into(),
-            data: SubscribeData {
-                channel: format!("order_book_{}", symbol),
-            },
+            data: SubscribeData { channel },
         }
     }
+
+    pub fn order_book(symbol: &str) -> Self {
+        Self::new(format!("order_book_{}", symbol))
+    }
+
+    pub fn live_trades(symbol: &str) -> Self {
+        Self::new(format!("live_trades_{}", symbol))
+    }
 }
 
 #[derive(Deserialize, Debug)]
 pub struct TraidingPairInfo {
     pub url_symbol: String,
+    /// e.g. "BTC/USD"
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub base_decimals: i32,
+    #[serde(default)]
+    pub counter_decimals: i32,
+    /// Minimum order value with currency, e.g. "10.00 USD"
+    #[serde(default)]
+    pub minimum_order: String,
+}
+
+impl From<&TraidingPairInfo> for crate::registry::SymbolRules {
+    fn from(info: &TraidingPairInfo) -> Self {
+        let mut assets = info.name.split('/');
+        Self {
+            base: assets.next().unwrap_or_default().to_string(),
+            quote: assets.next().unwrap_or_default().to_string(),
+            tick_size: 10f64.powi(-info.counter_decimals),
+            lot_size: 10f64.powi(-info.base_decimals),
+            min_quantity: 0.0,
+            min_notional: info
+                .minimum_order
+                .split_whitespace()
+                .next()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default(),
+        }
+    }
 }
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
@@ -43,18 +78,37 @@ pub struct OrderBook {
     pub asks: Vec<Order>,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+pub struct Trade {
+    pub id: u64,
+    #[serde(rename = "price_str", deserialize_with = "crate::de_float")]
+    pub price: f64,
+    #[serde(rename = "amount_str", deserialize_with = "crate::de_float")]
+    pub amount: f64,
+    /// 0 for buy, 1 for sell taker order
+    #[serde(rename = "type")]
+    pub side: u8,
+    pub microtimestamp: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum UpdateData {
+    OrderBook(OrderBook),
+    Trade(Trade),
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct UpdateMsg {
     pub event: String,
     #[allow(dead_code)]
     pub channel: String,
-    pub data: Option<OrderBook>,
+    pub data: Option<UpdateData>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct SubsMsg {
     pub event: String,
-    #[allow(dead_code)]
     pub channel: String,
 }
 
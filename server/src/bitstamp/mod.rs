@@ -2,9 +2,13 @@ use futures_util::stream::{SplitSink, SplitStream};
 use futures_util::{SinkExt, StreamExt};
 use tokio::sync::mpsc;
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, WebSocketStream};
+use tracing::{debug, info, info_span, trace, warn, Instrument};
 
 use crate::config::BitstampConfig;
-use crate::TrackerError;
+use crate::latency::now_us;
+use crate::registry::{ExchangeRegistry, SymbolRules};
+use crate::server as proto;
+use crate::{Exchange, TrackerError};
 
 use self::api::TraidingPairInfo;
 
@@ -15,9 +19,9 @@ type WsSink =
 type WsStream =
     SplitStream<WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>>;
 
-const EX_ENDPOINT: &str = "wss://ws.bitstamp.net";
 const EX_NAME: &str = "Bitstamp";
-const INFO_ENDPOINT: &str = "https://www.bitstamp.net/api/v2/trading-pairs-info/";
+const EXCHANGE: Exchange = Exchange::Bitstamp;
+const INFO_PATH: &str = "/api/v2/trading-pairs-info/";
 
 enum ConnectionStatus {
     Disconnected,
@@ -30,32 +34,59 @@ pub struct BitstampSubscriber {
     cfg: BitstampConfig,
     status: ConnectionStatus,
     tx: mpsc::UnboundedSender<crate::OrderBook>,
+    /// Trades channel subscribed if set
+    trades: Option<mpsc::UnboundedSender<crate::Trade>>,
+    /// Order book top published if set
+    bbo: Option<mpsc::UnboundedSender<crate::Bbo>>,
+    registry: ExchangeRegistry,
     ws: Option<(WsSink, WsStream)>,
     last_book: Option<api::OrderBook>,
+    attempt: u64,
 }
 
 impl BitstampSubscriber {
     pub fn new(
         cfg: BitstampConfig,
         tx: mpsc::UnboundedSender<crate::OrderBook>,
+        trades: Option<mpsc::UnboundedSender<crate::Trade>>,
+        bbo: Option<mpsc::UnboundedSender<crate::Bbo>>,
+        registry: ExchangeRegistry,
     ) -> Result<Self, String> {
+        // No top of book channel, the top of each order book frame is published
+        let bbo = bbo.filter(|_| cfg.bbo);
         Ok(Self {
             cfg,
             status: ConnectionStatus::Disconnected,
             tx,
+            trades,
+            bbo,
+            registry,
             ws: None,
             last_book: None,
+            attempt: 0,
         })
     }
 
     pub async fn run(&mut self) -> Result<(), TrackerError> {
+        let span = info_span!("exchange", exchange = EX_NAME, symbol = %self.cfg.symbol);
+        self.run_loop().instrument(span).await
+    }
+
+    async fn run_loop(&mut self) -> Result<(), TrackerError> {
         loop {
             if let Err(e) = self.process().await {
                 if let TrackerError::Cnnection(_e) = &e {
-                    eprintln!("{}: {:?}", EX_NAME, e);
+                    warn!(attempt = self.attempt, error = ?e, "Connection lost, retrying in 2s");
                     self.status = ConnectionStatus::Disconnected;
+                    self.registry
+                        .set_connection(EXCHANGE, proto::ConnectionState::Disconnected);
+                    // Book and top of book are unknown until reconnected
+                    self.last_book = None;
+                    let _ = self.tx.send(crate::OrderBook::cleared(EXCHANGE));
+                    if let Some(bbo) = &self.bbo {
+                        let _ = bbo.send(crate::Bbo::cleared(EXCHANGE));
+                    }
                     tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-                    println!("{}: Retrying.", EX_NAME);
                 } else {
                     return Err(e);
                 }
@@ -66,6 +97,9 @@ impl BitstampSubscriber {
     async fn process(&mut self) -> Result<(), TrackerError> {
         match &mut self.status {
             ConnectionStatus::Disconnected => {
+                self.attempt += 1;
+                self.registry
+                    .set_connection(EXCHANGE, proto::ConnectionState::Connecting);
                 self.check_config().await?;
                 self.connect().await?;
                 self.status = ConnectionStatus::Connected;
@@ -77,6 +111,9 @@ impl BitstampSubscriber {
             ConnectionStatus::SubscribtionSent => {
                 self.rcv_subscription_info().await?;
                 self.status = ConnectionStatus::Updating;
+                self.attempt = 0;
+                self.registry
+                    .set_connection(EXCHANGE, proto::ConnectionState::Connected);
             }
             ConnectionStatus::Updating => {
                 self.rcv_update().await?;
@@ -86,7 +123,8 @@ impl BitstampSubscriber {
     }
 
     async fn check_config(&self) -> Result<(), TrackerError> {
-        let resp = reqwest::get(INFO_ENDPOINT)
+        let url = format!("{}{}", self.cfg.rest_url().trim_end_matches('/'), INFO_PATH);
+        let resp = reqwest::get(&url)
             .await
             .map_err(|e| TrackerError::Other(format!("{}: Get info error: {}", EX_NAME, e)))?;
 
@@ -99,48 +137,73 @@ impl BitstampSubscriber {
             TrackerError::Other(format!("{}: Info response parse error: {}", EX_NAME, e))
         })?;
 
-        let symbols: Vec<String> = info.into_iter().map(|s| s.url_symbol).collect();
-        if !symbols.contains(&self.cfg.symbol.to_lowercase()) {
-            return Err(TrackerError::Config(format!(
-                "{}: Invalid symbol {} Valid symbols are:\n{:?}",
-                EX_NAME, self.cfg.symbol, symbols
-            )));
+        let symbol = self.cfg.symbol.to_lowercase();
+        match info.iter().find(|s| s.url_symbol == symbol) {
+            Some(pair) => self.registry.set_rules(EXCHANGE, SymbolRules::from(pair)),
+            None => {
+                let symbols: Vec<String> = info.into_iter().map(|s| s.url_symbol).collect();
+                return Err(TrackerError::Config(format!(
+                    "{}: Invalid symbol {} Valid symbols are:\n{:?}",
+                    EX_NAME, self.cfg.symbol, symbols
+                )));
+            }
         }
 
         Ok(())
     }
 
     async fn connect(&mut self) -> Result<(), TrackerError> {
-        println!("{}: Connecting", EX_NAME);
-        let (ws_stream, _) = connect_async(EX_ENDPOINT)
+        info!(attempt = self.attempt, "Connecting");
+        let (ws_stream, _) = connect_async(self.cfg.ws_url())
             .await
             .map_err(|e| format!("Ws Connection error {}", e))?;
-        println!(
-            "{}: WebSocket handshake has been successfully completed",
-            EX_NAME
+        info!(
+            attempt = self.attempt,
+            "WebSocket handshake has been successfully completed"
         );
         self.ws = Some(ws_stream.split());
 
         Ok(())
     }
 
+    fn channels(&self) -> Vec<api::SubscribeRequest> {
+        let symbol = self.cfg.symbol.to_lowercase();
+        let mut channels = vec![api::SubscribeRequest::order_book(&symbol)];
+        if self.trades.is_some() {
+            channels.push(api::SubscribeRequest::live_trades(&symbol));
+        }
+        channels
+    }
+
     async fn subscribe_to_channel(&mut self) -> Result<(), TrackerError> {
-        let req = api::SubscribeRequest::new(&self.cfg.symbol.to_lowercase());
-        let serialized = serde_json::to_string(&req).expect("Valid json");
-        self.ws
-            .as_mut()
-            .expect("Is connected")
-            .0
-            .send(serialized.into())
-            .await
-            .map_err(|e| {
-                TrackerError::Cnnection(format!("{}: Subscribe send error: {}", EX_NAME, e))
-            })?;
+        for req in self.channels() {
+            let serialized = serde_json::to_string(&req).expect("Valid json");
+            self.ws
+                .as_mut()
+                .expect("Is connected")
+                .0
+                .send(serialized.into())
+                .await
+                .map_err(|e| {
+                    TrackerError::Cnnection(format!("{}: Subscribe send error: {}", EX_NAME, e))
+                })?;
+        }
 
         Ok(())
     }
 
     async fn rcv_subscription_info(&mut self) -> Result<(), TrackerError> {
+        let mut pending = self.channels().len();
+        while pending > 0 {
+            if self.rcv_subscription_event().await? {
+                pending -= 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// True if a channel subscription was confirmed, false for early channel data
+    async fn rcv_subscription_event(&mut self) -> Result<bool, TrackerError> {
         let msg = self
             .ws
             .as_mut()
@@ -148,10 +211,7 @@ impl BitstampSubscriber {
             .1
             .next()
             .await
-            .ok_or_else( || TrackerError::Cnnection(format!(
-                "{}: Ws stream terminated",
-                EX_NAME
-            )))?
+            .ok_or_else(|| TrackerError::Cnnection(format!("{}: Ws stream terminated", EX_NAME)))?
             .map_err(|e| TrackerError::Cnnection(format!("Rcv error {}", e)))?
             .into_text()
             .map_err(|_e| format!("{}: Recieved msg is not a string", EX_NAME))?;
@@ -163,13 +223,16 @@ impl BitstampSubscriber {
             )
         })?;
 
-        if !event.event.contains("subscription_succeeded") {
-            return Err(format!("{}: Invalid subscription response: {:?}", EX_NAME, event).into());
+        if event.event.contains("subscription_succeeded") {
+            info!(channel = %event.channel, "Channel subscribed");
+            Ok(true)
+        } else if event.event == "data" || event.event == "trade" {
+            // Subscribed channel data before the other channel is confirmed
+            debug!(channel = %event.channel, "Data before subscription completed");
+            Ok(false)
         } else {
-            println!("{}: Channel subscribed", EX_NAME);
+            Err(format!("{}: Invalid subscription response: {:?}", EX_NAME, event).into())
         }
-
-        Ok(())
     }
 
     async fn rcv_update(&mut self) -> Result<(), TrackerError> {
@@ -182,9 +245,10 @@ impl BitstampSubscriber {
             .await
             .ok_or(format!("{}: Ws stream terminated", EX_NAME))?
             .map_err(|e| format!("{}: Rcv error {}", EX_NAME, e))?;
+        let received = now_us();
 
         if msg.is_ping() {
-            println!("{}: Ping", EX_NAME);
+            debug!("Ping");
             let data = msg.into_data();
             self.ws
                 .as_mut()
@@ -194,13 +258,13 @@ impl BitstampSubscriber {
                 .await
                 .map_err(|e| TrackerError::Cnnection(format!("{}: Send error {}", EX_NAME, e)))?;
 
-            println!("{}: Pong", EX_NAME);
+            debug!("Pong");
 
             return Ok(());
         }
 
         if msg.is_empty() || !msg.is_text() {
-            println!("{}: Empty update", EX_NAME);
+            debug!("Empty update");
             return Ok(());
         }
 
@@ -211,19 +275,41 @@ impl BitstampSubscriber {
         let event: api::UpdateMsg = serde_json::from_str(&text)
             .map_err(|e| format!("{}: Update info parse error: {}\n{}", EX_NAME, e, text))?;
 
-        let book = if let Some(book) = event.data {
-            book
-        } else {
-            return Err(format!("{}: Invalid event sequence", EX_NAME).into());
+        let parsed = now_us();
+        let book = match event.data {
+            Some(api::UpdateData::OrderBook(book)) => book,
+            Some(api::UpdateData::Trade(trade)) => {
+                trace!(id = trade.id, "Trade");
+                self.registry.touch(EXCHANGE);
+                if let Some(tx) = &self.trades {
+                    let trade = crate::Trade::from(trade).with_local_timestamps(received, parsed);
+                    tx.send(trade).map_err(|e| {
+                        TrackerError::Other(format!("{}: Trade send error: {}", EX_NAME, e))
+                    })?;
+                }
+                return Ok(());
+            }
+            None => return Err(format!("{}: Invalid event sequence", EX_NAME).into()),
         };
 
-        if let Some(u) = &self.last_book {
-            if book.changed(u) {
-                self.tx.send(book.clone().into()).unwrap();
-                self.last_book = Some(book);
+        trace!(microtimestamp = %book.microtimestamp, "Book update");
+
+        let changed = match &self.last_book {
+            Some(u) => book.changed(u),
+            None => true,
+        };
+
+        self.registry.touch_book(EXCHANGE);
+
+        if changed {
+            let update =
+                crate::OrderBook::from(book.clone()).with_local_timestamps(received, parsed);
+            if let Some((tx, bbo)) = self.bbo.as_ref().zip(update.bbo()) {
+                tx.send(bbo).map_err(|e| {
+                    TrackerError::Other(format!("{}: Bbo send error: {}", EX_NAME, e))
+                })?;
             }
-        } else {
-            self.tx.send(book.clone().into()).unwrap();
+            self.tx.send(update).unwrap();
             self.last_book = Some(book);
         }
 
@@ -0,0 +1,112 @@
+use crate::server::{level_change::Action, BookDiff, Level, LevelChange, Summary};
+
+/// Level identity within one side of the book
+fn same_level(a: &Level, b: &Level) -> bool {
+    a.exchange_id == b.exchange_id && a.price == b.price
+}
+
+/// Level changes transforming `old` summary into `new`
+pub fn diff(old: &Summary, new: &Summary) -> BookDiff {
+    BookDiff {
+        spread: new.spread,
+        bids: diff_side(&old.bids, &new.bids),
+        asks: diff_side(&old.asks, &new.asks),
+    }
+}
+
+/// Changes transforming `old` side into `new`, to be applied in order:
+/// deletes from the back first, then inserts and updates from the front.
+pub fn diff_side(old: &[Level], new: &[Level]) -> Vec<LevelChange> {
+    // Old levels present in new side, kept only if their relative order is unchanged
+    let mut kept = Vec::with_capacity(old.len());
+    let mut deleted = Vec::new();
+    for (i, level) in old.iter().enumerate() {
+        match new.iter().position(|n| same_level(n, level)) {
+            Some(j) if kept.last().map(|&(_, last)| j > last).unwrap_or(true) => kept.push((i, j)),
+            _ => deleted.push(i),
+        }
+    }
+
+    let mut changes: Vec<LevelChange> = deleted
+        .into_iter()
+        .rev()
+        .map(|i| LevelChange {
+            action: Action::Delete as i32,
+            index: i as u32,
+            level: None,
+        })
+        .collect();
+
+    let mut kept = kept.into_iter().peekable();
+    for (j, level) in new.iter().enumerate() {
+        match kept.peek() {
+            Some(&(i, target)) if target == j => {
+                kept.next();
+                if old[i] != *level {
+                    changes.push(LevelChange {
+                        action: Action::Update as i32,
+                        index: j as u32,
+                        level: Some(level.clone()),
+                    });
+                }
+            }
+            _ => changes.push(LevelChange {
+                action: Action::Insert as i32,
+                index: j as u32,
+                level: Some(level.clone()),
+            }),
+        }
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff_side;
+    use crate::server::{level_change::Action, ExchangeId, Level, LevelChange};
+    use crate::test_util::level;
+    use std::convert::TryFrom;
+
+    fn apply(side: &mut Vec<Level>, changes: &[LevelChange]) {
+        for c in changes {
+            let idx = c.index as usize;
+            match Action::try_from(c.action).unwrap() {
+                Action::Insert => side.insert(idx, c.level.clone().unwrap()),
+                Action::Update => side[idx] = c.level.clone().unwrap(),
+                Action::Delete => {
+                    side.remove(idx);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_diff_side() {
+        let old = vec![
+            level(ExchangeId::Binance, 10.0, 1.0),
+            level(ExchangeId::Bitstamp, 10.0, 2.0),
+            level(ExchangeId::Binance, 9.0, 1.0),
+            level(ExchangeId::Bitstamp, 8.0, 3.0),
+        ];
+        let new = vec![
+            level(ExchangeId::Bitstamp, 10.0, 2.0),
+            level(ExchangeId::Binance, 10.0, 1.5),
+            level(ExchangeId::Binance, 9.5, 1.0),
+            level(ExchangeId::Bitstamp, 8.0, 3.0),
+            level(ExchangeId::Binance, 7.0, 4.0),
+        ];
+
+        let changes = diff_side(&old, &new);
+        let mut side = old.clone();
+        apply(&mut side, &changes);
+        assert_eq!(side, new);
+
+        // Unchanged side produces no changes
+        assert!(diff_side(&new, &new).is_empty());
+
+        let mut side = new.clone();
+        apply(&mut side, &diff_side(&new, &[]));
+        assert!(side.is_empty());
+    }
+}
@@ -1,18 +1,890 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
 use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+
+use crate::TrackerError;
 
-#[derive(Deserialize, Debug, Clone)]
+/// Prefix of environment variables overriding configuration file settings,
+/// nested keys are separated by `__`, e.g. `EXCHANGE_TRACKER_BINANCE__SYMBOL`
+pub const ENV_PREFIX: &str = "EXCHANGE_TRACKER_";
+/// Configuration file path if not given on the command line
+pub const ENV_CONFIG_PATH: &str = "EXCHANGE_TRACKER_CONFIG";
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct BinanceConfig {
+    /// Connector started, can be changed without restart
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
     pub symbol: String,
+    /// Partial book depth stream levels: 5, 10 or 20
+    #[serde(default = "default_binance_depth")]
+    pub depth: u32,
+    /// Informational fee tier label, e.g. "VIP 0"
+    #[serde(default)]
+    pub fee_tier: String,
+    #[serde(default)]
+    pub fees: FeeConfig,
+    /// Trade stream subscribed if trades are enabled
+    #[serde(default)]
+    pub trade_stream: BinanceTradeStream,
+    /// Subscribe to real-time `@bookTicker` best bid and offer
+    #[serde(default)]
+    pub bbo: bool,
+    /// Endpoint preset, overridden by `endpoints`
+    #[serde(default)]
+    pub venue: BinanceVenue,
+    #[serde(default)]
+    pub endpoints: EndpointsConfig,
+}
+
+impl BinanceConfig {
+    /// REST API base URL
+    pub fn rest_url(&self) -> &str {
+        self.endpoints
+            .rest_url
+            .as_deref()
+            .unwrap_or_else(|| self.venue.rest_url())
+    }
+
+    /// WebSocket base URL
+    pub fn ws_url(&self) -> &str {
+        self.endpoints
+            .ws_url
+            .as_deref()
+            .unwrap_or_else(|| self.venue.ws_url())
+    }
+}
+
+/// Binance deployment whose endpoints are used by default
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BinanceVenue {
+    #[default]
+    Global,
+    /// Spot test network
+    Testnet,
+    /// Binance.US
+    Us,
+}
+
+impl BinanceVenue {
+    fn rest_url(self) -> &'static str {
+        match self {
+            BinanceVenue::Global => "https://api.binance.com",
+            BinanceVenue::Testnet => "https://testnet.binance.vision",
+            BinanceVenue::Us => "https://api.binance.us",
+        }
+    }
+
+    fn ws_url(self) -> &'static str {
+        match self {
+            BinanceVenue::Global => "wss://stream.binance.com:9443",
+            BinanceVenue::Testnet => "wss://stream.testnet.binance.vision",
+            BinanceVenue::Us => "wss://stream.binance.us:9443",
+        }
+    }
+}
+
+/// Exchange endpoint overrides, e.g. for mock servers or proxies
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct EndpointsConfig {
+    /// REST API base URL, API paths are appended
+    pub rest_url: Option<String>,
+    /// WebSocket URL, Binance stream paths are appended
+    pub ws_url: Option<String>,
+}
+
+fn default_binance_depth() -> u32 {
+    10
 }
 
-#[derive(Deserialize, Debug, Clone)]
+fn default_enabled() -> bool {
+    true
+}
+
+/// Binance trade stream flavour
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum BinanceTradeStream {
+    /// Every trade
+    #[default]
+    Trade,
+    /// Trades of one taker order at one price combined
+    AggTrade,
+}
+
+impl BinanceTradeStream {
+    /// Stream name suffix, e.g. `btcusdt@aggTrade`
+    pub fn name(self) -> &'static str {
+        match self {
+            BinanceTradeStream::Trade => "trade",
+            BinanceTradeStream::AggTrade => "aggTrade",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct BitstampConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
     pub symbol: String,
+    /// Informational fee tier label
+    #[serde(default)]
+    pub fee_tier: String,
+    #[serde(default)]
+    pub fees: FeeConfig,
+    /// Publish best bid and offer of every order book frame. Bitstamp has
+    /// no top of book channel, so these arrive no sooner than depth data.
+    #[serde(default)]
+    pub bbo: bool,
+    #[serde(default)]
+    pub endpoints: EndpointsConfig,
+}
+
+impl BitstampConfig {
+    /// REST API base URL
+    pub fn rest_url(&self) -> &str {
+        self.endpoints
+            .rest_url
+            .as_deref()
+            .unwrap_or("https://www.bitstamp.net")
+    }
+
+    /// WebSocket URL
+    pub fn ws_url(&self) -> &str {
+        self.endpoints
+            .ws_url
+            .as_deref()
+            .unwrap_or("wss://ws.bitstamp.net")
+    }
+}
+
+/// Trading fees as fractions of notional, e.g. 0.001 for 0.1%
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeeConfig {
+    pub maker: f64,
+    pub taker: f64,
+}
+
+/// Order book merge settings
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MergeConfig {
+    /// Rank and report levels by taker fee adjusted price,
+    /// raw exchange price is kept in `Level.raw_price`
+    pub fee_adjusted: bool,
+}
+
+/// Quote currency normalization settings
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FxConfig {
+    /// Currency merged prices are converted to, e.g. USDT. Disabled if empty.
+    pub reference: String,
+    /// Rates of exchange quote currencies other than the reference
+    pub rates: Vec<FxRateConfig>,
+}
+
+/// Conversion rate of one currency to the reference currency
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FxRateConfig {
+    /// Converted currency, e.g. USDC
+    pub currency: String,
+    /// Binance symbol whose mid price is the rate, e.g. USDCUSDT
+    #[serde(default)]
+    pub symbol: Option<String>,
+    /// Symbol is quoted in `currency`, e.g. USDTUSDC for USDC to USDT
+    #[serde(default)]
+    pub invert: bool,
+    /// Static rate used instead of a live book
+    #[serde(default)]
+    pub fixed: Option<f64>,
+}
+
+/// Per exchange book validation settings
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationConfig {
+    /// Validate books before merging
+    pub enabled: bool,
+    /// Maximum distance of best bid and ask from the consensus mid of other
+    /// exchanges, in basis points. 0 disables the check.
+    pub max_deviation_bps: f64,
+    /// Mids of other exchanges older than this are left out of the consensus
+    pub consensus_max_age_secs: u64,
+    pub action: RejectAction,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_deviation_bps: 500.0,
+            consensus_max_age_secs: 10,
+            action: RejectAction::default(),
+        }
+    }
+}
+
+/// Handling of books failing validation
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectAction {
+    /// Drop the book, last valid book of the exchange stays merged
+    Reject,
+    /// Remove the exchange from the merge until a valid book arrives
+    #[default]
+    Quarantine,
+}
+
+/// Cross-exchange opportunity detector settings
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArbitrageConfig {
+    /// Compute prices and profit including taker fees of both exchanges
+    pub net_of_fees: bool,
+    /// Opportunities with lower quote currency profit are ignored
+    pub min_profit: f64,
+}
+
+/// Derived market metrics settings
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Merged levels per side used for weighted mid and imbalance
+    pub top_levels: usize,
+    /// Distances from mid of cumulative depth bands, basis points
+    pub depth_bps: Vec<f64>,
+    /// Time weighted average windows
+    pub windows_secs: Vec<u32>,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            top_levels: 5,
+            depth_bps: vec![10.0, 25.0, 50.0, 100.0],
+            windows_secs: vec![10, 60, 300],
+        }
+    }
+}
+
+/// Time bar settings
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BarsConfig {
+    /// Bar lengths built from the merged book
+    pub intervals_secs: Vec<u32>,
+    /// Closed bars kept per interval
+    pub history: usize,
+}
+
+impl Default for BarsConfig {
+    fn default() -> Self {
+        Self {
+            intervals_secs: vec![1, 60, 300],
+            history: 1000,
+        }
+    }
+}
+
+/// Trade feed settings
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TradesConfig {
+    /// Subscribe to exchange trade streams
+    pub enabled: bool,
+    /// Rolling volume window
+    pub window_secs: u32,
+}
+
+impl Default for TradesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            window_secs: 60,
+        }
+    }
+}
+
+/// Alert engine settings
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertsConfig {
+    /// Rule evaluation period
+    pub eval_interval_ms: u64,
+    pub rules: Vec<AlertRule>,
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        Self {
+            eval_interval_ms: 100,
+            rules: vec![],
+        }
+    }
+}
+
+/// Alert raised when `condition` holds for `for_ms`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    pub name: String,
+    pub condition: AlertCondition,
+    /// Condition must hold this long before the alert fires
+    #[serde(default)]
+    pub for_ms: u64,
+    /// Minimum time between two alerts of the rule
+    #[serde(default)]
+    pub cooldown_secs: u64,
+    #[serde(default = "default_alert_actions")]
+    pub actions: Vec<AlertAction>,
 }
 
-#[derive(Deserialize, Debug)]
+fn default_alert_actions() -> Vec<AlertAction> {
+    vec![AlertAction::Log]
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum AlertCondition {
+    /// Merged book spread below `value`, e.g. 0 for a crossed book
+    SpreadBelow { value: f64 },
+    /// Merged book spread above `value`
+    SpreadAbove { value: f64 },
+    /// No book update from `exchange` for `secs`, trades and BBO ignored
+    Stale { exchange: String, secs: f64 },
+    /// Merged mid moved more than `percent` within `window_secs`
+    MidMove { percent: f64, window_secs: u64 },
+    /// Best prices of two exchanges differ more than `bps`
+    Divergence {
+        exchanges: [String; 2],
+        side: BookSide,
+        bps: f64,
+    },
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BookSide {
+    Bid,
+    Ask,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum AlertAction {
+    /// Warning log line
+    Log,
+    /// JSON POST of the alert
+    Webhook { url: String },
+    /// Local command, alert passed in `ALERT_*` environment variables
+    Command {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+/// Logging output settings
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// `tracing_subscriber::EnvFilter` directives, e.g.
+    /// `info,exchange_tracker::exchange_listener=warn`.
+    /// Overridden by the `RUST_LOG` environment variable if set.
+    pub level: String,
+    /// Emit newline delimited JSON instead of human readable lines
+    pub json: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".into(),
+            json: false,
+        }
+    }
+}
+
+/// gRPC server TLS settings, paths to PEM encoded files
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+    /// CA bundle used to verify client certificates. Enables mutual TLS if set.
+    #[serde(default)]
+    pub client_ca_path: Option<String>,
+}
+
+/// API key with its permissions
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyConfig {
+    /// Client name used in logs
+    pub name: String,
+    /// Secret sent as `authorization: Bearer <key>` or `x-api-key: <key>` metadata
+    pub key: String,
+    /// Instruments the key may subscribe to, all if empty
+    #[serde(default)]
+    pub instruments: Vec<String>,
+    /// Maximum levels per side
+    #[serde(default)]
+    pub max_depth: Option<u32>,
+    /// Maximum concurrently open streams
+    #[serde(default)]
+    pub max_streams: Option<usize>,
+}
+
+/// Client authentication settings
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    pub keys: Vec<ApiKeyConfig>,
+}
+
+/// `BookUpdates` stream settings
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BookUpdatesConfig {
+    /// Full snapshot is sent instead of a diff if the last one is older
+    pub checkpoint_interval_secs: u64,
+}
+
+impl Default for BookUpdatesConfig {
+    fn default() -> Self {
+        Self {
+            checkpoint_interval_secs: 10,
+        }
+    }
+}
+
+/// Latency measurement settings
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LatencyConfig {
+    /// Add per-message `Latency` field to streamed summaries
+    pub summary_fields: bool,
+    /// Interval of latency percentiles log report, 0 disables it
+    pub report_interval_secs: u64,
+}
+
+impl Default for LatencyConfig {
+    fn default() -> Self {
+        Self {
+            summary_fields: false,
+            report_interval_secs: 60,
+        }
+    }
+}
+
+/// Health check settings
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    /// An exchange without book updates for this long is not fresh
+    pub max_update_age_secs: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            max_update_age_secs: 10,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct ServerConfig {
     pub grpc_listen_addr: String,
+    /// Name of the merged instrument, defaults to Binance symbol
+    #[serde(default)]
+    pub instrument: String,
+    /// Plaintext if not set
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// WebSocket/JSON gateway address, disabled if not set
+    #[serde(default)]
+    pub ws_listen_addr: Option<String>,
+    /// REST/JSON gateway address, disabled if not set
+    #[serde(default)]
+    pub http_listen_addr: Option<String>,
     pub binance: BinanceConfig,
     pub bitstamp: BitstampConfig,
+    #[serde(default)]
+    pub merge: MergeConfig,
+    #[serde(default)]
+    pub fx: FxConfig,
+    #[serde(default)]
+    pub validation: ValidationConfig,
+    #[serde(default)]
+    pub arbitrage: ArbitrageConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub bars: BarsConfig,
+    #[serde(default)]
+    pub trades: TradesConfig,
+    #[serde(default)]
+    pub alerts: AlertsConfig,
+    #[serde(default)]
+    pub log: LogConfig,
+    #[serde(default)]
+    pub latency: LatencyConfig,
+    #[serde(default)]
+    pub book_updates: BookUpdatesConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    /// Unauthenticated access if not set
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+}
+
+impl ServerConfig {
+    /// Merged instrument name
+    pub fn instrument(&self) -> &str {
+        if self.instrument.is_empty() {
+            &self.binance.symbol
+        } else {
+            &self.instrument
+        }
+    }
+
+    /// Problems parsing does not catch, checked without connecting anywhere
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        let addrs = [
+            ("grpc_listen_addr", Some(&self.grpc_listen_addr)),
+            ("ws_listen_addr", self.ws_listen_addr.as_ref()),
+            ("http_listen_addr", self.http_listen_addr.as_ref()),
+        ];
+        let mut bound: Vec<(&str, SocketAddr)> = vec![];
+        for (name, addr) in addrs.iter().filter_map(|(n, a)| Some((*n, (*a)?))) {
+            match addr.parse::<SocketAddr>() {
+                Ok(a) => {
+                    if let Some((other, _)) = bound.iter().find(|(_, b)| *b == a) {
+                        problems.push(format!("{} {} is also used by {}", name, addr, other));
+                    }
+                    bound.push((name, a));
+                }
+                Err(e) => problems.push(format!("{}: invalid address {}: {}", name, addr, e)),
+            }
+        }
+
+        if self.health.max_update_age_secs == 0 {
+            problems.push("health.max_update_age_secs must be at least 1".to_string());
+        }
+        if !self.binance.enabled && !self.bitstamp.enabled {
+            problems.push("no exchange enabled".to_string());
+        }
+        for (name, symbol) in [
+            ("binance", &self.binance.symbol),
+            ("bitstamp", &self.bitstamp.symbol),
+        ] {
+            if symbol.is_empty() {
+                problems.push(format!("{}.symbol is empty", name));
+            }
+        }
+        let urls = [
+            (
+                "binance.endpoints.rest_url",
+                self.binance.rest_url(),
+                "http",
+            ),
+            ("binance.endpoints.ws_url", self.binance.ws_url(), "ws"),
+            (
+                "bitstamp.endpoints.rest_url",
+                self.bitstamp.rest_url(),
+                "http",
+            ),
+            ("bitstamp.endpoints.ws_url", self.bitstamp.ws_url(), "ws"),
+        ];
+        for (name, url, scheme) in urls {
+            match reqwest::Url::parse(url) {
+                Ok(u) if u.scheme() == scheme || u.scheme() == format!("{}s", scheme) => {}
+                Ok(_) => problems.push(format!("{} {} is not a {} URL", name, url, scheme)),
+                Err(e) => problems.push(format!("{}: invalid URL {}: {}", name, url, e)),
+            }
+        }
+        if !crate::binance::DEPTH_LEVELS.contains(&self.binance.depth) {
+            problems.push(format!(
+                "binance.depth {} is not one of {:?}",
+                self.binance.depth,
+                crate::binance::DEPTH_LEVELS
+            ));
+        }
+
+        if !self.fx.reference.is_empty() {
+            if let Err(e) = crate::fx::FxRates::new(&self.fx) {
+                problems.push(format!("fx: {}", e));
+            }
+        }
+        problems.extend(crate::alerts::check_rules(&self.alerts));
+
+        let lists: [(&str, Vec<String>); 4] = [
+            (
+                "fx.rates currency",
+                self.fx
+                    .rates
+                    .iter()
+                    .map(|r| r.currency.to_uppercase())
+                    .collect(),
+            ),
+            (
+                "metrics.depth_bps",
+                self.metrics.depth_bps.iter().map(f64::to_string).collect(),
+            ),
+            (
+                "metrics.windows_secs",
+                self.metrics
+                    .windows_secs
+                    .iter()
+                    .map(u32::to_string)
+                    .collect(),
+            ),
+            (
+                "bars.intervals_secs",
+                self.bars
+                    .intervals_secs
+                    .iter()
+                    .map(u32::to_string)
+                    .collect(),
+            ),
+        ];
+        for (name, values) in lists.iter() {
+            for value in duplicates(values) {
+                problems.push(format!("{} {} is listed more than once", name, value));
+            }
+        }
+
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.log.level) {
+            problems.push(format!("log.level {}: {}", self.log.level, e));
+        }
+        if let Some(tls) = &self.tls {
+            let paths = [
+                ("tls.cert_path", Some(&tls.cert_path)),
+                ("tls.key_path", Some(&tls.key_path)),
+                ("tls.client_ca_path", tls.client_ca_path.as_ref()),
+            ];
+            for (name, path) in paths.iter().filter_map(|(n, p)| Some((*n, (*p)?))) {
+                if !Path::new(path).is_file() {
+                    problems.push(format!("{}: {} not found", name, path));
+                }
+            }
+        }
+
+        if let Some(auth) = &self.auth {
+            for (i, key) in auth.keys.iter().enumerate() {
+                let previous = &auth.keys[..i];
+                if previous.iter().any(|k| k.name == key.name) {
+                    problems.push(format!("auth key name {} is used more than once", key.name));
+                }
+                if previous.iter().any(|k| k.key == key.key) {
+                    problems.push(format!(
+                        "auth key {} reuses the secret of another key",
+                        key.name
+                    ));
+                }
+                for instrument in duplicates(&key.instruments) {
+                    problems.push(format!(
+                        "auth key {}: duplicate instrument {}",
+                        key.name, instrument
+                    ));
+                }
+                for (j, instrument) in key.instruments.iter().enumerate() {
+                    if key.instruments[..j].contains(instrument) {
+                        continue;
+                    }
+                    if instrument != self.instrument() {
+                        problems.push(format!(
+                            "auth key {}: unknown instrument {}, tracked instrument is {}",
+                            key.name,
+                            instrument,
+                            self.instrument()
+                        ));
+                    }
+                }
+            }
+        }
+
+        problems
+    }
+}
+
+/// Values listed more than once, each reported once
+fn duplicates<T: PartialEq>(values: &[T]) -> Vec<&T> {
+    values
+        .iter()
+        .enumerate()
+        .filter(|(i, v)| values[..*i].iter().filter(|p| p == v).count() == 1)
+        .map(|(_, v)| v)
+        .collect()
+}
+
+/// Configuration file with `EXCHANGE_TRACKER_*` environment variable and
+/// command line overrides, applied in that order
+#[derive(Debug, Clone)]
+pub struct ConfigSource {
+    pub path: PathBuf,
+    /// `key.path=value` overrides, e.g. `binance.symbol=ETHUSDT`
+    pub overrides: Vec<String>,
+}
+
+impl ConfigSource {
+    pub fn new(path: &Path, overrides: Vec<String>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            overrides,
+        }
+    }
+
+    pub fn load(&self) -> Result<ServerConfig, TrackerError> {
+        self.load_with_env(std::env::vars())
+    }
+
+    fn load_with_env(
+        &self,
+        env: impl Iterator<Item = (String, String)>,
+    ) -> Result<ServerConfig, TrackerError> {
+        let path = self.path.display();
+        let str = std::fs::read_to_string(&self.path)
+            .map_err(|e| TrackerError::Config(format!("Failed to read {}: {}", path, e)))?;
+        let mut value: Value = serde_yaml::from_str(&str)
+            .map_err(|e| TrackerError::Config(format!("Failed to parse {}: {}", path, e)))?;
+
+        let mut env: Vec<(String, String)> = env
+            .filter(|(k, _)| k != ENV_CONFIG_PATH)
+            .filter_map(|(k, v)| Some((k.strip_prefix(ENV_PREFIX)?.to_string(), v)))
+            .collect();
+        env.sort();
+        for (key, raw) in env {
+            set_path(&mut value, &key.to_lowercase().replace("__", "."), &raw)
+                .map_err(|e| TrackerError::Config(format!("{}{}: {}", ENV_PREFIX, key, e)))?;
+        }
+        for o in &self.overrides {
+            let (key, raw) = o
+                .split_once('=')
+                .ok_or_else(|| TrackerError::Config(format!("Override {} is not key=value", o)))?;
+            set_path(&mut value, key, raw)
+                .map_err(|e| TrackerError::Config(format!("Override {}: {}", o, e)))?;
+        }
+
+        serde_yaml::from_value(value)
+            .map_err(|e| TrackerError::Config(format!("Invalid configuration {}: {}", path, e)))
+    }
+}
+
+/// Sets the value at a dotted key path, `raw` is parsed as YAML.
+/// Missing sections are created, list items are selected by index.
+fn set_path(root: &mut Value, key: &str, raw: &str) -> Result<(), String> {
+    let value = match raw {
+        "" => Value::String(String::new()),
+        raw => serde_yaml::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string())),
+    };
+    let mut node = root;
+    for part in key.split('.') {
+        if part.is_empty() {
+            return Err(format!("invalid key {}", key));
+        }
+        if node.is_null() {
+            *node = Value::Mapping(Mapping::new());
+        }
+        node = match node {
+            Value::Mapping(m) => m
+                .entry(Value::String(part.to_string()))
+                .or_insert(Value::Null),
+            Value::Sequence(s) => part
+                .parse::<usize>()
+                .ok()
+                .and_then(move |i| s.get_mut(i))
+                .ok_or_else(|| format!("{} is not an index of {}", part, key))?,
+            _ => return Err(format!("{} of {} is not a section", part, key)),
+        };
+    }
+    *node = value;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConfigSource;
+    use std::io::Write;
+
+    #[test]
+    fn test_layered_config() {
+        let path = std::env::temp_dir().join(format!("tracker-config-{}.yml", std::process::id()));
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(
+            b"grpc_listen_addr: 127.0.0.1:12345
+binance: { symbol: BTCUSDT }
+bitstamp: { symbol: BTCUSDT, fees: { taker: 0.004 } }
+alerts:
+  rules:
+    - { name: stale, condition: { type: stale, exchange: kraken, secs: 5 } }
+",
+        )
+        .unwrap();
+
+        let env = vec![
+            ("EXCHANGE_TRACKER_GRPC_LISTEN_ADDR", "0.0.0.0:5000"),
+            ("EXCHANGE_TRACKER_BINANCE__DEPTH", "20"),
+            ("EXCHANGE_TRACKER_BITSTAMP__FEES__MAKER", "0.003"),
+            ("EXCHANGE_TRACKER_ALERTS__RULES__0__FOR_MS", "250"),
+            ("EXCHANGE_TRACKER_CONFIG", "other.yml"),
+            ("HOME", "/root"),
+        ];
+        let source = ConfigSource::new(
+            &path,
+            vec![
+                "binance.symbol=ETHUSDT".into(),
+                "binance.venue=testnet".into(),
+                "bitstamp.endpoints.ws_url=ws://127.0.0.1:9000".into(),
+                "ws_listen_addr=0.0.0.0:5000".into(),
+            ],
+        );
+        let cfg = source
+            .load_with_env(env.into_iter().map(|(k, v)| (k.into(), v.into())))
+            .unwrap();
+        assert_eq!(cfg.grpc_listen_addr, "0.0.0.0:5000");
+        assert_eq!(
+            (cfg.binance.symbol.as_str(), cfg.binance.depth),
+            ("ETHUSDT", 20)
+        );
+        assert_eq!(
+            (cfg.bitstamp.fees.maker, cfg.bitstamp.fees.taker),
+            (0.003, 0.004)
+        );
+        assert_eq!(cfg.alerts.rules[0].for_ms, 250);
+        assert_eq!(cfg.binance.rest_url(), "https://testnet.binance.vision");
+        assert_eq!(cfg.bitstamp.rest_url(), "https://www.bitstamp.net");
+        assert_eq!(cfg.bitstamp.ws_url(), "ws://127.0.0.1:9000");
+
+        let problems = cfg.validate();
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].contains("also used by grpc_listen_addr"));
+        assert!(problems[1].contains("kraken"));
+
+        let bad = ConfigSource::new(&path, vec!["binance.symbol.x=1".into()]);
+        assert!(bad.load_with_env(std::iter::empty()).is_err());
+        // Mistyped keys are rejected, also from the environment
+        let typo = ConfigSource::new(&path, vec![]);
+        let env = vec![("EXCHANGE_TRACKER_BINANCE__DEPHT".into(), "5".into())];
+        assert!(typo.load_with_env(env.into_iter()).is_err());
+        let lists = ConfigSource::new(&path, vec!["bars.intervals_secs=[60, 1, 60]".into()]);
+        let problems = lists.load_with_env(std::iter::empty()).unwrap().validate();
+        assert!(problems
+            .iter()
+            .any(|p| p == "bars.intervals_secs 60 is listed more than once"));
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,248 @@
+use strum::{EnumCount, IntoEnumIterator};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::binance::BinanceSubscriber;
+use crate::bitstamp::BitstampSubscriber;
+use crate::config::{BinanceConfig, BitstampConfig, FeeConfig, ServerConfig};
+use crate::registry::ExchangeRegistry;
+use crate::server::ConnectionState;
+use crate::{Bbo, Exchange, OrderBook, TrackerError, Trade};
+
+/// Connector settings, a connector is restarted when they change
+#[derive(Debug, Clone, PartialEq)]
+enum Settings {
+    Binance(BinanceConfig, bool),
+    Bitstamp(BitstampConfig, bool),
+}
+
+impl Settings {
+    /// Settings of an enabled exchange, fees and fee tier are left out
+    /// as connectors do not use them
+    fn new(cfg: &ServerConfig, exchange: Exchange) -> Option<Self> {
+        let trades = cfg.trades.enabled;
+        match exchange {
+            Exchange::Binance if cfg.binance.enabled => Some(Settings::Binance(
+                BinanceConfig {
+                    fee_tier: String::new(),
+                    fees: FeeConfig::default(),
+                    ..cfg.binance.clone()
+                },
+                trades,
+            )),
+            Exchange::Bitstamp if cfg.bitstamp.enabled => Some(Settings::Bitstamp(
+                BitstampConfig {
+                    fee_tier: String::new(),
+                    fees: FeeConfig::default(),
+                    ..cfg.bitstamp.clone()
+                },
+                trades,
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Connector task of one exchange
+struct Task {
+    generation: u64,
+    /// Started with the startup configuration
+    initial: bool,
+    handle: JoinHandle<()>,
+}
+
+type Done = (Exchange, u64, Result<(), TrackerError>);
+
+/// Starts, stops and restarts exchange connectors as the configuration changes
+pub struct ConnectorSupervisor {
+    config: watch::Receiver<ServerConfig>,
+    tx: mpsc::UnboundedSender<OrderBook>,
+    trades: mpsc::UnboundedSender<Trade>,
+    bbo: mpsc::UnboundedSender<Bbo>,
+    registry: ExchangeRegistry,
+    /// Indexed by `Exchange`
+    settings: Vec<Option<Settings>>,
+    tasks: Vec<Option<Task>>,
+    generation: u64,
+}
+
+impl ConnectorSupervisor {
+    pub fn new(
+        config: watch::Receiver<ServerConfig>,
+        tx: mpsc::UnboundedSender<OrderBook>,
+        trades: mpsc::UnboundedSender<Trade>,
+        bbo: mpsc::UnboundedSender<Bbo>,
+        registry: ExchangeRegistry,
+    ) -> Self {
+        Self {
+            config,
+            tx,
+            trades,
+            bbo,
+            registry,
+            settings: vec![None; Exchange::COUNT],
+            tasks: (0..Exchange::COUNT).map(|_| None).collect(),
+            generation: 0,
+        }
+    }
+
+    /// Fails if a connector started with the startup configuration fails,
+    /// failures after a reload stop the connector until its settings change
+    pub async fn run(mut self) -> Result<(), TrackerError> {
+        let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+        let mut initial = true;
+        let mut watching = true;
+        loop {
+            let cfg = self.config.borrow_and_update().clone();
+            self.registry.configure(&cfg);
+            for exchange in Exchange::iter() {
+                let settings = Settings::new(&cfg, exchange);
+                if settings == self.settings[exchange as usize] {
+                    continue;
+                }
+                let started = match &settings {
+                    Some(s) => self.start(s, initial, done_tx.clone()),
+                    None => {
+                        self.stop(exchange);
+                        Ok(())
+                    }
+                };
+                if let Err(e) = started {
+                    if initial {
+                        return Err(e);
+                    }
+                    error!(
+                        exchange = exchange.name(),
+                        error = ?e,
+                        "Connector not restarted, running connector kept"
+                    );
+                    continue;
+                }
+                self.settings[exchange as usize] = settings;
+            }
+            initial = false;
+
+            loop {
+                tokio::select! {
+                    changed = self.config.changed(), if watching => {
+                        if changed.is_err() {
+                            // Reload disabled
+                            watching = false;
+                            continue;
+                        }
+                        break;
+                    }
+                    Some((exchange, generation, result)) = done_rx.recv() => {
+                        self.finished(exchange, generation, result)?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Replaces the running connector of the exchange, which is left
+    /// untouched if the new one cannot be created
+    fn start(
+        &mut self,
+        settings: &Settings,
+        initial: bool,
+        done: mpsc::UnboundedSender<Done>,
+    ) -> Result<(), TrackerError> {
+        self.generation += 1;
+        let generation = self.generation;
+        let bbo = Some(self.bbo.clone());
+        let (exchange, handle) = match settings.clone() {
+            Settings::Binance(cfg, trades) => {
+                let trades = trades.then(|| self.trades.clone());
+                let mut subscriber = BinanceSubscriber::new(
+                    cfg,
+                    self.tx.clone(),
+                    trades,
+                    bbo,
+                    self.registry.clone(),
+                )
+                .map_err(TrackerError::Config)?;
+                self.stop(Exchange::Binance);
+                let handle = tokio::spawn(async move {
+                    let result = subscriber.run().await;
+                    let _ = done.send((Exchange::Binance, generation, result));
+                });
+                (Exchange::Binance, handle)
+            }
+            Settings::Bitstamp(cfg, trades) => {
+                let trades = trades.then(|| self.trades.clone());
+                let mut subscriber = BitstampSubscriber::new(
+                    cfg,
+                    self.tx.clone(),
+                    trades,
+                    bbo,
+                    self.registry.clone(),
+                )
+                .map_err(TrackerError::Config)?;
+                self.stop(Exchange::Bitstamp);
+                let handle = tokio::spawn(async move {
+                    let result = subscriber.run().await;
+                    let _ = done.send((Exchange::Bitstamp, generation, result));
+                });
+                (Exchange::Bitstamp, handle)
+            }
+        };
+        if !initial {
+            info!(exchange = exchange.name(), "Connector started");
+        }
+        self.tasks[exchange as usize] = Some(Task {
+            generation,
+            initial,
+            handle,
+        });
+        Ok(())
+    }
+
+    /// Stops the connector and removes the exchange from merged feeds
+    fn stop(&mut self, exchange: Exchange) {
+        let task = match self.tasks[exchange as usize].take() {
+            Some(task) => task,
+            None => return,
+        };
+        task.handle.abort();
+        info!(exchange = exchange.name(), "Connector stopped");
+        self.clear(exchange);
+    }
+
+    fn clear(&self, exchange: Exchange) {
+        self.registry
+            .set_connection(exchange, ConnectionState::Disconnected);
+        // Empty book and cleared BBO take the exchange out of the merge
+        let _ = self.tx.send(OrderBook::cleared(exchange));
+        let _ = self.bbo.send(Bbo::cleared(exchange));
+    }
+
+    fn finished(
+        &mut self,
+        exchange: Exchange,
+        generation: u64,
+        result: Result<(), TrackerError>,
+    ) -> Result<(), TrackerError> {
+        let slot = &mut self.tasks[exchange as usize];
+        let initial = match slot {
+            Some(task) if task.generation == generation => task.initial,
+            // Already replaced
+            _ => return Ok(()),
+        };
+        *slot = None;
+        match result {
+            Err(e) if initial => Err(e),
+            Err(e) => {
+                error!(
+                    exchange = exchange.name(),
+                    error = ?e,
+                    "Connector failed, restarted when its settings change"
+                );
+                self.clear(exchange);
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        }
+    }
+}
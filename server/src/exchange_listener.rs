@@ -1,7 +1,10 @@
 use strum::EnumCount;
 use tokio::sync::{mpsc, watch};
+use tracing::{debug, info, trace};
 
-use crate::server::Summary;
+use crate::fees::FeeSchedule;
+use crate::latency::{now_us, LatencyRecorder, Stage};
+use crate::server::{Latency, Level, Summary, VenueTrades};
 use crate::{Exchange, TrackerError};
 
 /// Maximum asks and bids size in Summary data
@@ -10,28 +13,72 @@ pub const MAX_DEPTH: usize = 10;
 pub struct ExchangeListener {
     rx: mpsc::UnboundedReceiver<crate::OrderBook>,
     tx: watch::Sender<Summary>,
-    books: Vec<crate::OrderBook>,
+    /// Latest full book of each exchange, indexed by `Exchange`
+    books: watch::Sender<Vec<crate::OrderBook>>,
+    latency: LatencyRecorder,
+    /// Fee adjusted merge if set
+    fees: Option<FeeSchedule>,
+    /// Whether last merge produced a valid summary
+    valid: watch::Sender<bool>,
+    /// Trade stats attached to published summaries
+    trades: watch::Receiver<Vec<VenueTrades>>,
 }
 
 impl ExchangeListener {
-    pub fn new(rx: mpsc::UnboundedReceiver<crate::OrderBook>, tx: watch::Sender<Summary>) -> Self {
+    pub fn new(
+        rx: mpsc::UnboundedReceiver<crate::OrderBook>,
+        tx: watch::Sender<Summary>,
+        latency: LatencyRecorder,
+        fees: Option<FeeSchedule>,
+        trades: watch::Receiver<Vec<VenueTrades>>,
+    ) -> Self {
         Self {
             rx,
             tx,
-            books: vec![crate::OrderBook::default(); Exchange::COUNT],
+            books: watch::channel(vec![crate::OrderBook::default(); Exchange::COUNT]).0,
+            latency,
+            fees,
+            valid: watch::channel(false).0,
+            trades,
         }
     }
 
+    /// Merged book validity - false until bids and asks are available
+    pub fn subscribe_valid(&self) -> watch::Receiver<bool> {
+        self.valid.subscribe()
+    }
+
+    /// Per exchange books as received, not limited to `MAX_DEPTH`
+    pub fn subscribe_books(&self) -> watch::Receiver<Vec<crate::OrderBook>> {
+        self.books.subscribe()
+    }
+
     pub async fn run(&mut self) -> Result<(), TrackerError> {
-        println!("Listener: running");
+        info!("Listener running");
         let mut last_summary = None;
         loop {
             if let Some(book) = self.rx.recv().await {
-                println!("Listener: Received update from {:?}", book.exchange);
-                let idx = book.exchange as usize;
-                self.books[idx] = book;
+                trace!(exchange = ?book.exchange, "Received update");
+                let exchange = book.exchange;
+                let ts = book.timestamps;
+                if let Some(event) = ts.event {
+                    self.latency
+                        .record(exchange, Stage::Network, event, ts.received);
+                }
+                self.latency
+                    .record(exchange, Stage::Parse, ts.received, ts.parsed);
+
+                let idx = exchange as usize;
+                self.books.send_modify(|books| books[idx] = book);
 
-                match Self::merge(&self.books) {
+                let result = Self::merge(&self.books.borrow(), self.fees.as_ref());
+                self.valid.send_if_modified(|valid| {
+                    let modified = *valid != result.is_ok();
+                    *valid = result.is_ok();
+                    modified
+                });
+
+                match result {
                     Ok(v) => {
                         let send = if let Some(last) = &last_summary {
                             // Broadcast only if changed
@@ -41,7 +88,21 @@ impl ExchangeListener {
                         };
 
                         if send {
-                            if let Err(_e) = self.tx.send(v.clone()) {
+                            let merged = now_us();
+                            self.latency
+                                .record(exchange, Stage::Merge, ts.parsed, merged);
+                            let mut out = v.clone();
+                            out.latency = Some(Latency {
+                                exchange: exchange.name().to_string(),
+                                exchange_id: exchange.id() as i32,
+                                event_time_us: ts.event.unwrap_or_default(),
+                                received_us: ts.received,
+                                parsed_us: ts.parsed,
+                                merged_us: merged,
+                                sent_us: 0,
+                            });
+                            out.trades = self.trades.borrow().clone();
+                            if let Err(_e) = self.tx.send(out) {
                                 // No more receivers - app is shutting down
                                 break;
                             }
@@ -51,7 +112,7 @@ impl ExchangeListener {
                     Err(e) => {
                         // Spread calculation error - either bids or asks vec is empty
                         // Not sending update
-                        eprintln!("{}", e);
+                        debug!(error = %e, "Summary not published");
                     }
                 }
             }
@@ -59,48 +120,68 @@ impl ExchangeListener {
         Ok(())
     }
 
-    /// Merges partial order books into summary
-    fn merge(books: &Vec<crate::OrderBook>) -> Result<Summary, String> {
-        let mut bids = Vec::with_capacity(MAX_DEPTH);
-        let mut asks = Vec::with_capacity(MAX_DEPTH);
+    /// Merges partial order books into summary, ranking levels by
+    /// taker fee adjusted prices if `fees` are given
+    fn merge(books: &[crate::OrderBook], fees: Option<&FeeSchedule>) -> Result<Summary, String> {
+        let bids = Self::merge_side(books, fees, true, MAX_DEPTH);
+        let asks = Self::merge_side(books, fees, false, MAX_DEPTH);
 
-        let mut iters = vec![0usize; books.len()];
-        while bids.len() < MAX_DEPTH {
-            let mut best = None;
-            for i in 0..books.len() {
-                if let Some(order) = books[i].bids.get(iters[i]) {
-                    if let Some(b) = best {
-                        let book: &crate::OrderBook = &books[b];
-                        if order.better(&book.bids[iters[b]], true) {
-                            best = Some(i);
-                        }
-                    } else {
-                        best = Some(i);
-                    }
-                }
-            }
-            if let Some(idx) = best {
-                let level = crate::server::Level {
-                    exchange: format!("{:?}", books[idx].bids[iters[idx]].exchange),
-                    price: books[idx].bids[iters[idx]].price,
-                    amount: books[idx].bids[iters[idx]].quantity,
-                };
-                bids.push(level);
-                iters[idx] += 1;
-            } else {
-                // Not enought bids in source
-                break;
-            }
-        }
+        let spread = if !asks.is_empty() && !bids.is_empty() {
+            asks[0].price - bids[0].price
+        } else {
+            return Err("Spread undefined".into());
+        };
+
+        Ok(Summary {
+            asks,
+            bids,
+            spread,
+            latency: None,
+            fee_adjusted: fees.is_some(),
+            trades: vec![],
+            quote: books
+                .iter()
+                .find_map(|b| b.conversion.as_ref())
+                .map(|c| c.reference.clone())
+                .unwrap_or_default(),
+        })
+    }
 
-        let mut iters = vec![0usize; books.len()];
-        while asks.len() < MAX_DEPTH {
-            let mut best = None;
-            for i in 0..books.len() {
-                if let Some(order) = books[i].asks.get(iters[i]) {
+    /// Best `depth` levels of one side across all books
+    pub fn merge_side(
+        books: &[crate::OrderBook],
+        fees: Option<&FeeSchedule>,
+        bids: bool,
+        depth: usize,
+    ) -> Vec<Level> {
+        let raw: Vec<&[crate::Order]> = books
+            .iter()
+            .map(|b| if bids { &b.bids[..] } else { &b.asks[..] })
+            .collect();
+        // Fees scale prices of a venue uniformly so each side stays sorted
+        let sides: Vec<Vec<crate::Order>> = raw
+            .iter()
+            .map(|side| {
+                side.iter()
+                    .take(depth)
+                    .map(|o| crate::Order {
+                        price: fees
+                            .map(|f| f.taker_price(o.exchange, o.price, bids))
+                            .unwrap_or(o.price),
+                        ..o.clone()
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut levels = Vec::with_capacity(sides.iter().map(Vec::len).sum());
+        let mut iters = vec![0usize; sides.len()];
+        while levels.len() < depth {
+            let mut best: Option<usize> = None;
+            for i in 0..sides.len() {
+                if let Some(order) = sides[i].get(iters[i]) {
                     if let Some(b) = best {
-                        let book: &crate::OrderBook = &books[b];
-                        if order.better(&book.asks[iters[b]], false) {
+                        if order.better(&sides[b][iters[b]], bids) {
                             best = Some(i);
                         }
                     } else {
@@ -109,32 +190,39 @@ impl ExchangeListener {
                 }
             }
             if let Some(idx) = best {
-                let level = crate::server::Level {
-                    exchange: format!("{:?}", books[idx].asks[iters[idx]].exchange),
-                    price: books[idx].asks[iters[idx]].price,
-                    amount: books[idx].asks[iters[idx]].quantity,
+                let order = &sides[idx][iters[idx]];
+                let raw_price = raw[idx][iters[idx]].price;
+                let (original_quote, original_price) = match &books[idx].conversion {
+                    Some(c) => (c.quote.clone(), raw_price / c.rate),
+                    None => (String::new(), 0.0),
                 };
-                asks.push(level);
+                levels.push(Level {
+                    // Cleared per subscriber with `omit_exchange_names`
+                    exchange: order.exchange.name().to_string(),
+                    exchange_id: order.exchange.id() as i32,
+                    price: order.price,
+                    amount: order.quantity,
+                    raw_price,
+                    venues: vec![],
+                    original_quote,
+                    original_price,
+                });
                 iters[idx] += 1;
             } else {
-                // Not enought asks in source
+                // Not enought orders in source
                 break;
             }
         }
-
-        let spread = if !asks.is_empty() && !bids.is_empty() {
-            asks[0].price - bids[0].price
-        } else {
-            return Err("Spread undefined".into());
-        };
-
-        Ok(Summary { asks, bids, spread })
+        levels
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{exchange_listener::ExchangeListener, Exchange, Order, OrderBook};
+    use crate::{
+        config::FeeConfig, exchange_listener::ExchangeListener, fees::FeeSchedule,
+        server::ExchangeId, Exchange, Order, OrderBook,
+    };
 
     #[test]
     fn test_megre() {
@@ -142,17 +230,21 @@ mod tests {
             exchange: Exchange::Bitstamp,
             bids: vec![],
             asks: vec![],
+            timestamps: Default::default(),
+            conversion: None,
         };
 
         let book2 = OrderBook {
             exchange: Exchange::Binance,
             bids: vec![],
             asks: vec![],
+            timestamps: Default::default(),
+            conversion: None,
         };
 
         let mut books = vec![book1, book2];
 
-        assert!(ExchangeListener::merge(&books).is_err());
+        assert!(ExchangeListener::merge(&books, None).is_err());
 
         books[0].asks.push(Order {
             price: 10.1,
@@ -238,14 +330,61 @@ mod tests {
             exchange: Exchange::Binance,
         });
 
-        let merged = ExchangeListener::merge(&books).unwrap();
+        let merged = ExchangeListener::merge(&books, None).unwrap();
 
         assert!(merged.spread - 0.9 < f64::EPSILON * 10.0);
-        assert!(merged.bids[0].exchange == "Binance");
+        assert!(merged.bids[0].exchange_id == ExchangeId::Binance as i32);
         assert!(merged.bids[0].amount - 1.0 < f64::EPSILON * 10.0);
-        assert!(merged.bids[1].exchange == "Bitstamp");
+        assert!(merged.bids[1].exchange_id == ExchangeId::Bitstamp as i32);
         assert!(merged.bids[1].amount - 1.2 < f64::EPSILON * 10.0);
-        assert!(merged.bids[2].exchange == "Bitstamp");
+        assert!(merged.bids[2].exchange_id == ExchangeId::Bitstamp as i32);
         assert!(merged.bids[3].amount - 77.0 < f64::EPSILON * 10.0);
     }
+
+    #[test]
+    fn test_merge_fee_adjusted() {
+        let order = |exchange, price, id| Order {
+            price,
+            quantity: 1.0,
+            id,
+            exchange,
+        };
+        let books = vec![
+            OrderBook {
+                exchange: Exchange::Binance,
+                bids: vec![order(Exchange::Binance, 9.0, 1)],
+                asks: vec![order(Exchange::Binance, 10.0, 2)],
+                timestamps: Default::default(),
+                conversion: None,
+            },
+            OrderBook {
+                exchange: Exchange::Bitstamp,
+                bids: vec![order(Exchange::Bitstamp, 8.995, 3)],
+                asks: vec![order(Exchange::Bitstamp, 10.005, 4)],
+                timestamps: Default::default(),
+                conversion: None,
+            },
+        ];
+        let fees = FeeSchedule {
+            fees: vec![
+                FeeConfig {
+                    maker: 0.0,
+                    taker: 0.001,
+                },
+                FeeConfig::default(),
+            ],
+        };
+
+        let raw = ExchangeListener::merge(&books, None).unwrap();
+        assert_eq!(raw.asks[0].exchange_id, ExchangeId::Binance as i32);
+        assert!(!raw.fee_adjusted);
+
+        let merged = ExchangeListener::merge(&books, Some(&fees)).unwrap();
+        assert!(merged.fee_adjusted);
+        assert_eq!(merged.asks[0].exchange_id, ExchangeId::Bitstamp as i32);
+        assert_eq!(merged.bids[0].exchange_id, ExchangeId::Bitstamp as i32);
+        assert_eq!(merged.asks[1].raw_price, 10.0);
+        assert!((merged.asks[1].price - 10.01).abs() < 1e-9);
+        assert!((merged.spread - (10.005 - 8.995)).abs() < 1e-9);
+    }
 }
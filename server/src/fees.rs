@@ -0,0 +1,60 @@
+use strum::{EnumCount, IntoEnumIterator};
+
+use crate::config::{FeeConfig, ServerConfig};
+use crate::Exchange;
+
+/// Configured maker/taker fees of all exchanges
+#[derive(Debug, Clone)]
+pub struct FeeSchedule {
+    pub(crate) fees: Vec<FeeConfig>,
+}
+
+impl FeeSchedule {
+    pub fn new(cfg: &ServerConfig) -> Self {
+        let mut fees = vec![FeeConfig::default(); Exchange::COUNT];
+        for exchange in Exchange::iter() {
+            fees[exchange as usize] = match exchange {
+                Exchange::Binance => cfg.binance.fees,
+                Exchange::Bitstamp => cfg.bitstamp.fees,
+            };
+        }
+        Self { fees }
+    }
+
+    pub fn get(&self, exchange: Exchange) -> FeeConfig {
+        self.fees[exchange as usize]
+    }
+
+    /// Price paid (asks) or received (bids) per unit when taking liquidity
+    pub fn taker_price(&self, exchange: Exchange, price: f64, bid: bool) -> f64 {
+        let fee = self.get(exchange).taker;
+        if bid {
+            price * (1.0 - fee)
+        } else {
+            price * (1.0 + fee)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FeeSchedule;
+    use crate::config::FeeConfig;
+    use crate::Exchange;
+
+    #[test]
+    fn test_taker_price() {
+        let fees = FeeSchedule {
+            fees: vec![
+                FeeConfig {
+                    maker: 0.0,
+                    taker: 0.001,
+                },
+                FeeConfig::default(),
+            ],
+        };
+        assert!((fees.taker_price(Exchange::Binance, 10.0, false) - 10.01).abs() < 1e-9);
+        assert!((fees.taker_price(Exchange::Binance, 10.0, true) - 9.99).abs() < 1e-9);
+        assert_eq!(fees.taker_price(Exchange::Bitstamp, 10.0, false), 10.0);
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use futures_util::{SinkExt, StreamExt};
+use strum::EnumCount;
+use tokio::sync::mpsc;
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use tracing::{debug, info, info_span, trace, warn, Instrument};
+
+use crate::binance::{api, stream_url};
+use crate::config::{FxConfig, FxRateConfig};
+use crate::registry::ExchangeRegistry;
+use crate::{Bbo, Exchange, OrderBook, QuoteConversion, TrackerError, Trade};
+
+/// Conversion rates to the reference currency, updated by `FxTracker`
+#[derive(Clone)]
+pub struct FxRates {
+    reference: String,
+    /// Reference currency units per currency unit, by upper case currency
+    rates: Arc<RwLock<HashMap<String, f64>>>,
+}
+
+impl FxRates {
+    pub fn new(cfg: &FxConfig) -> Result<Self, TrackerError> {
+        let reference = cfg.reference.to_uppercase();
+        let mut rates = HashMap::new();
+        rates.insert(reference.clone(), 1.0);
+        for rate in &cfg.rates {
+            match (&rate.symbol, rate.fixed) {
+                (None, Some(fixed)) if fixed > 0.0 => {
+                    rates.insert(rate.currency.to_uppercase(), fixed);
+                }
+                (Some(_), None) => {}
+                _ => {
+                    return Err(TrackerError::Config(format!(
+                        "FX rate of {} needs either a symbol or a positive fixed rate",
+                        rate.currency
+                    )))
+                }
+            }
+        }
+        Ok(Self {
+            reference,
+            rates: Arc::new(RwLock::new(rates)),
+        })
+    }
+
+    pub fn reference(&self) -> &str {
+        &self.reference
+    }
+
+    /// Reference currency units per `currency` unit, `None` until a live rate arrives
+    pub fn get(&self, currency: &str) -> Option<f64> {
+        let rates = self.rates.read().expect("FX lock poisoned");
+        rates.get(&currency.to_uppercase()).copied()
+    }
+
+    fn set(&self, currency: &str, rate: f64) {
+        let mut rates = self.rates.write().expect("FX lock poisoned");
+        rates.insert(currency.to_uppercase(), rate);
+    }
+}
+
+/// Tracks live conversion rates from Binance book tickers
+pub struct FxTracker {
+    /// Rates with a live symbol
+    live: Vec<FxRateConfig>,
+    rates: FxRates,
+    /// Binance WebSocket base URL
+    ws_url: String,
+}
+
+impl FxTracker {
+    pub fn new(cfg: &FxConfig, rates: FxRates, ws_url: &str) -> Self {
+        Self {
+            live: cfg
+                .rates
+                .iter()
+                .filter(|r| r.symbol.is_some())
+                .cloned()
+                .collect(),
+            rates,
+            ws_url: ws_url.to_string(),
+        }
+    }
+
+    pub async fn run(self) {
+        if self.live.is_empty() {
+            return;
+        }
+        let span = info_span!("fx");
+        async {
+            loop {
+                if let Err(e) = self.stream().await {
+                    warn!(error = ?e, "FX stream lost, retrying in 2s");
+                    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+                }
+            }
+        }
+        .instrument(span)
+        .await
+    }
+
+    fn symbol(rate: &FxRateConfig) -> String {
+        rate.symbol.as_deref().unwrap_or_default().to_lowercase()
+    }
+
+    async fn stream(&self) -> Result<(), TrackerError> {
+        let streams: Vec<String> = self
+            .live
+            .iter()
+            .map(|r| format!("{}@bookTicker", Self::symbol(r)))
+            .collect();
+        let (mut ws, _) = connect_async(&stream_url(&self.ws_url, &streams))
+            .await
+            .map_err(|e| TrackerError::Cnnection(format!("FX: Ws Connection error {}", e)))?;
+        info!("FX stream connected");
+
+        loop {
+            let msg = ws
+                .next()
+                .await
+                .ok_or_else(|| TrackerError::Cnnection("FX: Ws stream terminated".into()))?
+                .map_err(|e| TrackerError::Cnnection(format!("FX: Rcv error {}", e)))?;
+            if msg.is_ping() {
+                debug!("Ping");
+                ws.send(Message::Pong(msg.into_data()))
+                    .await
+                    .map_err(|e| TrackerError::Cnnection(format!("FX: Send error {}", e)))?;
+                continue;
+            }
+            if !msg.is_text() {
+                continue;
+            }
+            let text = msg
+                .into_text()
+                .map_err(|e| TrackerError::Other(format!("FX: Msg to text error {}", e)))?;
+            let msg: api::StreamMsg = serde_json::from_str(&text)
+                .map_err(|e| TrackerError::Other(format!("FX: Parse error: {}\n{}", e, text)))?;
+            if let api::StreamData::BookTicker(ticker) = msg.data {
+                let symbol = msg.stream.split('@').next().unwrap_or_default();
+                let mid = (ticker.bid_price + ticker.ask_price) / 2.0;
+                for rate in self.live.iter().filter(|r| Self::symbol(r) == symbol) {
+                    if mid > 0.0 {
+                        let value = if rate.invert { 1.0 / mid } else { mid };
+                        trace!(currency = %rate.currency, rate = value, "FX rate");
+                        self.rates.set(&rate.currency, value);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Exchange data priced in the exchange quote currency
+pub trait Quoted: Sized {
+    /// Name used in log messages
+    const KIND: &'static str;
+
+    fn exchange(&self) -> Exchange;
+
+    /// Without prices to convert, e.g. a cleared book
+    fn is_empty(&self) -> bool;
+
+    /// Converts prices with the rate of `conversion`
+    fn convert(&mut self, conversion: QuoteConversion);
+}
+
+impl Quoted for OrderBook {
+    const KIND: &'static str = "book";
+
+    fn exchange(&self) -> Exchange {
+        self.exchange
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+
+    fn convert(&mut self, conversion: QuoteConversion) {
+        for order in self.bids.iter_mut().chain(self.asks.iter_mut()) {
+            order.price *= conversion.rate;
+        }
+        self.conversion = Some(conversion);
+    }
+}
+
+impl Quoted for Bbo {
+    const KIND: &'static str = "BBO";
+
+    fn exchange(&self) -> Exchange {
+        self.exchange
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bid_quantity == 0.0 && self.ask_quantity == 0.0
+    }
+
+    fn convert(&mut self, conversion: QuoteConversion) {
+        self.bid_price *= conversion.rate;
+        self.ask_price *= conversion.rate;
+    }
+}
+
+impl Quoted for Trade {
+    const KIND: &'static str = "trade";
+
+    fn exchange(&self) -> Exchange {
+        self.exchange
+    }
+
+    fn is_empty(&self) -> bool {
+        false
+    }
+
+    fn convert(&mut self, conversion: QuoteConversion) {
+        self.price *= conversion.rate;
+    }
+}
+
+/// Converts books, BBOs or trades to the reference currency between
+/// connectors and their consumers. Data without a known rate is dropped.
+pub struct FxNormalizer<T> {
+    rx: mpsc::UnboundedReceiver<T>,
+    tx: mpsc::UnboundedSender<T>,
+    rates: FxRates,
+    registry: ExchangeRegistry,
+    /// Exchange data dropped for a missing rate, logged once
+    missing: Vec<bool>,
+}
+
+impl<T: Quoted> FxNormalizer<T> {
+    pub fn new(
+        rx: mpsc::UnboundedReceiver<T>,
+        tx: mpsc::UnboundedSender<T>,
+        rates: FxRates,
+        registry: ExchangeRegistry,
+    ) -> Self {
+        Self {
+            rx,
+            tx,
+            rates,
+            registry,
+            missing: vec![false; Exchange::COUNT],
+        }
+    }
+
+    pub async fn run(mut self) {
+        while let Some(item) = self.rx.recv().await {
+            let exchange = item.exchange();
+            let quote = self.registry.rules(exchange).quote;
+            let converted = convert(item, &quote, &self.rates);
+            let missing = &mut self.missing[exchange as usize];
+            match converted {
+                Some(item) => {
+                    if *missing {
+                        info!(exchange = exchange.name(), %quote, kind = T::KIND, "FX rate available");
+                        *missing = false;
+                    }
+                    if self.tx.send(item).is_err() {
+                        // Consumer dropped - app is shutting down
+                        break;
+                    }
+                }
+                None if !*missing => {
+                    warn!(
+                        exchange = exchange.name(),
+                        %quote,
+                        reference = self.rates.reference(),
+                        kind = T::KIND,
+                        "No FX rate, dropped"
+                    );
+                    *missing = true;
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+/// Prices in the reference currency, `None` if `quote` has no rate.
+/// Empty data is passed unconverted.
+pub fn convert<T: Quoted>(mut item: T, quote: &str, rates: &FxRates) -> Option<T> {
+    if item.is_empty() {
+        return Some(item);
+    }
+    let rate = rates.get(quote).filter(|_| !quote.is_empty())?;
+    item.convert(QuoteConversion {
+        quote: quote.to_uppercase(),
+        reference: rates.reference().to_string(),
+        rate,
+    });
+    Some(item)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{convert, FxRates};
+    use crate::config::FxConfig;
+    use crate::{Bbo, Exchange, Order, OrderBook};
+
+    #[test]
+    fn test_convert() {
+        let cfg: FxConfig = serde_yaml::from_str(
+            r#"
+reference: USDT
+rates:
+  - { currency: USD, fixed: 1.001 }
+  - { currency: USDC, symbol: USDCUSDT }
+"#,
+        )
+        .unwrap();
+        let rates = FxRates::new(&cfg).unwrap();
+        let book = OrderBook {
+            exchange: Exchange::Bitstamp,
+            bids: vec![Order {
+                price: 100.0,
+                quantity: 1.0,
+                id: 0,
+                exchange: Exchange::Bitstamp,
+            }],
+            ..Default::default()
+        };
+
+        let usd = convert(book.clone(), "usd", &rates).unwrap();
+        assert!((usd.bids[0].price - 100.1).abs() < 1e-9);
+        let conversion = usd.conversion.unwrap();
+        assert_eq!(conversion.quote, "USD");
+        assert_eq!(conversion.reference, "USDT");
+
+        // Live rate not received yet
+        assert!(convert(book.clone(), "USDC", &rates).is_none());
+        rates.set("USDC", 0.999);
+        assert!((convert(book.clone(), "USDC", &rates).unwrap().bids[0].price - 99.9).abs() < 1e-9);
+        assert_eq!(convert(book, "USDT", &rates).unwrap().bids[0].price, 100.0);
+
+        let bbo = Bbo {
+            exchange: Exchange::Bitstamp,
+            bid_price: 100.0,
+            bid_quantity: 1.0,
+            ask_price: 101.0,
+            ask_quantity: 1.0,
+            timestamps: Default::default(),
+        };
+        let bbo = convert(bbo, "USD", &rates).unwrap();
+        assert!((bbo.ask_price - 101.101).abs() < 1e-9);
+        // Cleared BBO passes without a rate
+        assert!(convert(Bbo::cleared(Exchange::Bitstamp), "EUR", &rates).is_some());
+
+        let invalid: FxConfig =
+            serde_yaml::from_str("reference: USDT\nrates: [{ currency: USD }]").unwrap();
+        assert!(FxRates::new(&invalid).is_err());
+    }
+}
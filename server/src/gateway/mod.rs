@@ -0,0 +1,126 @@
+use std::str::FromStr;
+
+use axum::http::HeaderMap;
+use url::form_urlencoded;
+
+use crate::auth::bearer_token;
+use crate::server::{aggregation::Mode, BookRequest};
+use crate::Exchange;
+
+pub mod rest;
+pub mod ws;
+
+/// Book subscription parameters of non-gRPC transports, mirroring `BookRequest`:
+/// `depth`, `instrument`, `exchanges` (comma separated names), `omit_exchange_names`
+/// and `aggregate` (`none`, `price` or `tick` with `tick` or `tick_bps` width).
+/// API key may be passed as `token`.
+#[derive(Debug, Default)]
+pub struct BookQuery {
+    pub request: BookRequest,
+    pub token: Option<String>,
+}
+
+impl BookQuery {
+    pub fn parse(query: &str) -> Result<Self, tonic::Status> {
+        let mut q = Self::default();
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            match key.as_ref() {
+                "depth" => {
+                    q.request.depth = value.parse().map_err(|_| {
+                        tonic::Status::invalid_argument(format!("Invalid depth {}", value))
+                    })?
+                }
+                "instrument" => q.request.instrument = value.into_owned(),
+                "exchanges" => {
+                    for name in value.split(',').filter(|n| !n.is_empty()) {
+                        let exchange = Exchange::from_str(name).map_err(|_| {
+                            tonic::Status::invalid_argument(format!("Unknown exchange {}", name))
+                        })?;
+                        q.request.exchanges.push(exchange.id() as i32);
+                    }
+                }
+                "aggregate" => {
+                    let mode = match value.as_ref() {
+                        "none" => Mode::None,
+                        "price" => Mode::Price,
+                        "tick" => Mode::Tick,
+                        _ => {
+                            return Err(tonic::Status::invalid_argument(format!(
+                                "Unknown aggregation {}",
+                                value
+                            )))
+                        }
+                    };
+                    q.request
+                        .aggregation
+                        .get_or_insert_with(Default::default)
+                        .mode = mode as i32;
+                }
+                "tick" | "tick_bps" => {
+                    let width = value.parse().map_err(|_| {
+                        tonic::Status::invalid_argument(format!("Invalid {} {}", key, value))
+                    })?;
+                    let aggregation = q.request.aggregation.get_or_insert_with(Default::default);
+                    if key == "tick" {
+                        aggregation.tick = width;
+                    } else {
+                        aggregation.tick_bps = width;
+                    }
+                }
+                "omit_exchange_names" => q.request.omit_exchange_names = value != "false",
+                "token" => q.token = Some(value.into_owned()),
+                _ => {}
+            }
+        }
+        Ok(q)
+    }
+}
+
+/// API key from `authorization: Bearer` or `x-api-key` header
+pub fn header_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(bearer_token)
+        .or_else(|| headers.get("x-api-key").and_then(|v| v.to_str().ok()))
+}
+
+/// HTTP status code equivalent of gRPC status
+pub fn http_status(status: &tonic::Status) -> u16 {
+    match status.code() {
+        tonic::Code::InvalidArgument => 400,
+        tonic::Code::Unauthenticated => 401,
+        tonic::Code::PermissionDenied => 403,
+        tonic::Code::NotFound => 404,
+        tonic::Code::ResourceExhausted => 429,
+        tonic::Code::Unavailable => 503,
+        _ => 500,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BookQuery;
+    use crate::server::{aggregation::Mode, ExchangeId};
+
+    #[test]
+    fn test_parse_query() {
+        let q = BookQuery::parse("depth=5&exchanges=binance,Bitstamp&token=abc").unwrap();
+        assert_eq!(q.request.depth, 5);
+        assert_eq!(
+            q.request.exchanges,
+            vec![ExchangeId::Binance as i32, ExchangeId::Bitstamp as i32]
+        );
+        assert_eq!(q.token.as_deref(), Some("abc"));
+        assert!(!q.request.omit_exchange_names);
+
+        let q = BookQuery::parse("aggregate=tick&tick_bps=1.5").unwrap();
+        let aggregation = q.request.aggregation.unwrap();
+        assert_eq!(aggregation.mode, Mode::Tick as i32);
+        assert_eq!(aggregation.tick_bps, 1.5);
+
+        assert!(BookQuery::parse("depth=x").is_err());
+        assert!(BookQuery::parse("aggregate=vwap").is_err());
+        assert!(BookQuery::parse("exchanges=kraken").is_err());
+    }
+}
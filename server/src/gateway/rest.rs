@@ -0,0 +1,123 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::{RawQuery, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::auth::Authenticator;
+use crate::gateway::{header_token, http_status, BookQuery};
+use crate::server::orderbook_aggregator_server::OrderbookAggregator;
+use crate::server::{Empty, OrderbookServer};
+use crate::TrackerError;
+
+/// Plain HTTP/JSON API for scripts and tools without gRPC support:
+/// `GET /book` (same query as the WebSocket gateway), `GET /exchanges`, `GET /health`.
+/// Served by the same `OrderbookServer` instance as the gRPC service.
+#[derive(Clone)]
+pub struct RestGateway {
+    server: Arc<OrderbookServer>,
+    auth: Authenticator,
+    serving: watch::Receiver<bool>,
+}
+
+/// gRPC status converted to HTTP response
+struct ApiError(tonic::Status);
+
+impl From<tonic::Status> for ApiError {
+    fn from(status: tonic::Status) -> Self {
+        Self(status)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let code =
+            StatusCode::from_u16(http_status(&self.0)).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let body = Json(serde_json::json!({ "error": self.0.message() }));
+        (code, body).into_response()
+    }
+}
+
+impl RestGateway {
+    pub fn new(
+        server: Arc<OrderbookServer>,
+        auth: Authenticator,
+        serving: watch::Receiver<bool>,
+    ) -> Self {
+        Self {
+            server,
+            auth,
+            serving,
+        }
+    }
+
+    pub async fn run(self, addr: SocketAddr) -> Result<(), TrackerError> {
+        let app = Router::new()
+            .route("/book", get(book))
+            .route("/exchanges", get(exchanges))
+            .route("/health", get(health))
+            .with_state(self);
+
+        let server = axum::Server::try_bind(&addr)
+            .map_err(|e| TrackerError::Config(format!("HTTP bind error {}: {}", addr, e)))?;
+        info!(%addr, "REST gateway listening");
+        server
+            .serve(app.into_make_service())
+            .await
+            .map_err(|e| TrackerError::Other(format!("HTTP server error: {}", e)))
+    }
+}
+
+async fn book(
+    State(gw): State<RestGateway>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let query = BookQuery::parse(query.as_deref().unwrap_or_default())?;
+    let token = query.token.as_deref().or_else(|| header_token(&headers));
+    let permissions = gw
+        .auth
+        .authenticate(token)
+        .inspect_err(|e| warn!(error = %e.message(), "REST authentication failed"))?;
+    let summary = gw.server.snapshot(&query.request, permissions.as_ref())?;
+    Ok(Json(summary))
+}
+
+async fn exchanges(
+    State(gw): State<RestGateway>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let query = BookQuery::parse(query.as_deref().unwrap_or_default())?;
+    let token = query.token.as_deref().or_else(|| header_token(&headers));
+    gw.auth
+        .authenticate(token)
+        .inspect_err(|e| warn!(error = %e.message(), "REST authentication failed"))?;
+    let list = gw
+        .server
+        .list_exchanges(tonic::Request::new(Empty {}))
+        .await?
+        .into_inner();
+    Ok(Json(list))
+}
+
+/// 200 while the merged book is valid and an exchange is connected and fresh,
+/// 503 otherwise
+async fn health(State(gw): State<RestGateway>) -> impl IntoResponse {
+    if *gw.serving.borrow() {
+        (
+            StatusCode::OK,
+            Json(serde_json::json!({ "status": "SERVING" })),
+        )
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "status": "NOT_SERVING" })),
+        )
+    }
+}
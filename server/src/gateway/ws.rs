@@ -0,0 +1,139 @@
+use std::net::SocketAddr;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http;
+use tokio_tungstenite::{accept_hdr_async, tungstenite::protocol::Message};
+use tracing::{debug, info, info_span, warn, Instrument};
+
+use crate::auth::{Authenticator, ClientPermissions, StreamGuard};
+use crate::gateway::{header_token, http_status, BookQuery};
+use crate::server::Summary;
+use crate::subscription::{Subscription, SubscriptionPolicy};
+use crate::{OrderBook, TrackerError};
+
+/// Streams merged summary as JSON text messages to WebSocket clients.
+/// Subscription parameters are passed in the URL query, e.g.
+/// `ws://host:port/?depth=5&exchanges=binance&token=<key>`.
+#[derive(Clone)]
+pub struct WsGateway {
+    rx: watch::Receiver<Summary>,
+    books: watch::Receiver<Vec<OrderBook>>,
+    auth: Authenticator,
+    policy: SubscriptionPolicy,
+}
+
+impl WsGateway {
+    pub fn new(
+        rx: watch::Receiver<Summary>,
+        books: watch::Receiver<Vec<OrderBook>>,
+        auth: Authenticator,
+        policy: SubscriptionPolicy,
+    ) -> Self {
+        Self {
+            rx,
+            books,
+            auth,
+            policy,
+        }
+    }
+
+    pub async fn run(self, addr: SocketAddr) -> Result<(), TrackerError> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| TrackerError::Config(format!("WebSocket bind error {}: {}", addr, e)))?;
+        info!(%addr, "WebSocket gateway listening");
+
+        loop {
+            let (stream, peer) = listener
+                .accept()
+                .await
+                .map_err(|e| TrackerError::Other(format!("WebSocket accept error: {}", e)))?;
+            let gateway = self.clone();
+            tokio::spawn(
+                gateway
+                    .serve(stream)
+                    .instrument(info_span!("ws_client", %peer)),
+            );
+        }
+    }
+
+    /// Validates handshake request and creates client subscription
+    fn accept(&self, req: &Request) -> Result<(Subscription, Option<StreamGuard>), tonic::Status> {
+        let query = BookQuery::parse(req.uri().query().unwrap_or_default())?;
+        let token = query
+            .token
+            .as_deref()
+            .or_else(|| header_token(req.headers()));
+
+        let permissions = self.auth.authenticate(token)?;
+        let subscription = self
+            .policy
+            .subscribe(&query.request, permissions.as_ref())?;
+        let guard = permissions
+            .as_ref()
+            .map(ClientPermissions::acquire_stream)
+            .transpose()?;
+        Ok((subscription, guard))
+    }
+
+    async fn serve(self, stream: TcpStream) {
+        let mut accepted = None;
+        let callback = |req: &Request, resp: Response| -> Result<Response, ErrorResponse> {
+            match self.accept(req) {
+                Ok(a) => {
+                    accepted = Some(a);
+                    Ok(resp)
+                }
+                Err(status) => {
+                    warn!(error = %status.message(), "Subscription rejected");
+                    let mut err = ErrorResponse::new(Some(status.message().to_string()));
+                    *err.status_mut() = http::StatusCode::from_u16(http_status(&status))
+                        .unwrap_or(http::StatusCode::INTERNAL_SERVER_ERROR);
+                    Err(err)
+                }
+            }
+        };
+
+        let ws = match accept_hdr_async(stream, callback).await {
+            Ok(ws) => ws,
+            Err(e) => {
+                debug!(error = %e, "Handshake failed");
+                return;
+            }
+        };
+        // Stream slot is released when the client disconnects
+        let (subscription, _guard) = match accepted {
+            Some(a) => a,
+            None => return,
+        };
+        debug!("Client subscribed");
+
+        let (mut sink, mut source) = ws.split();
+        let mut rx = self.rx.clone();
+        loop {
+            tokio::select! {
+                changed = rx.changed() => {
+                    if changed.is_err() {
+                        // Listener has dropped app is shutting down
+                        break;
+                    }
+                    let summary = subscription.view(&rx.borrow(), &self.books.borrow());
+                    let json = serde_json::to_string(&summary).expect("Summary is serializable");
+                    if let Err(e) = sink.send(Message::Text(json)).await {
+                        debug!(error = %e, "Send error");
+                        break;
+                    }
+                }
+                msg = source.next() => match msg {
+                    // Pings are answered by tungstenite, other messages ignored
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+        debug!("Client disconnected");
+    }
+}
@@ -0,0 +1,164 @@
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+use tracing::info;
+
+use crate::config::ServerConfig;
+use crate::registry::ExchangeRegistry;
+use crate::server::orderbook_aggregator_server::OrderbookAggregatorServer;
+use crate::server::OrderbookServer;
+
+/// Exchange freshness is re-checked this often
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Serving when the merged book is valid and at least one exchange
+/// is connected with a book update within `max_age`
+pub fn serving(valid: bool, registry: &ExchangeRegistry, max_age: Duration) -> bool {
+    valid && registry.live(max_age.as_micros() as u64)
+}
+
+/// Publishes the serving status to gRPC health and the REST gateway
+pub struct HealthMonitor {
+    config: watch::Receiver<ServerConfig>,
+    valid: watch::Receiver<bool>,
+    registry: ExchangeRegistry,
+    tx: watch::Sender<bool>,
+    /// `health.max_update_age_secs` of the live configuration
+    max_age: Duration,
+}
+
+impl HealthMonitor {
+    pub fn new(
+        mut config: watch::Receiver<ServerConfig>,
+        valid: watch::Receiver<bool>,
+        registry: ExchangeRegistry,
+    ) -> Self {
+        let max_age = Duration::from_secs(config.borrow_and_update().health.max_update_age_secs);
+        Self {
+            config,
+            valid,
+            registry,
+            tx: watch::channel(false).0,
+            max_age,
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.tx.subscribe()
+    }
+
+    pub async fn run(mut self) {
+        let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+        let mut watching = true;
+        loop {
+            tokio::select! {
+                changed = self.config.changed(), if watching => {
+                    if changed.is_err() {
+                        // Reload disabled
+                        watching = false;
+                        continue;
+                    }
+                    let secs = self.config.borrow_and_update().health.max_update_age_secs;
+                    self.max_age = Duration::from_secs(secs);
+                }
+                _ = ticker.tick() => {}
+                changed = self.valid.changed() => {
+                    if changed.is_err() {
+                        // Listener dropped - app is shutting down
+                        self.tx.send_replace(false);
+                        break;
+                    }
+                }
+            }
+            let current = serving(
+                *self.valid.borrow_and_update(),
+                &self.registry,
+                self.max_age,
+            );
+            self.tx.send_if_modified(|last| {
+                let modified = *last != current;
+                *last = current;
+                modified
+            });
+        }
+    }
+}
+
+/// Keeps `grpc.health.v1.Health` status in sync with the monitor.
+/// Both the overall server status ("") and the `OrderbookAggregator` service
+/// report NOT_SERVING until the merged book is valid and an exchange is
+/// connected and fresh.
+pub async fn run(mut reporter: HealthReporter, mut serving: watch::Receiver<bool>) {
+    let mut last = None;
+    loop {
+        let current = *serving.borrow_and_update();
+        if last != Some(current) {
+            let status = if current {
+                ServingStatus::Serving
+            } else {
+                ServingStatus::NotServing
+            };
+            info!(?status, "Health status changed");
+            reporter.set_service_status("", status).await;
+            if current {
+                reporter
+                    .set_serving::<OrderbookAggregatorServer<OrderbookServer>>()
+                    .await;
+            } else {
+                reporter
+                    .set_not_serving::<OrderbookAggregatorServer<OrderbookServer>>()
+                    .await;
+            }
+            last = Some(current);
+        }
+
+        if serving.changed().await.is_err() {
+            // Monitor dropped - app is shutting down
+            reporter
+                .set_service_status("", ServingStatus::NotServing)
+                .await;
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::serving;
+    use crate::config::ServerConfig;
+    use crate::registry::ExchangeRegistry;
+    use crate::server::ConnectionState;
+    use crate::Exchange;
+
+    #[test]
+    fn test_serving() {
+        let cfg: ServerConfig = serde_yaml::from_str(
+            r#"
+grpc_listen_addr: 127.0.0.1:0
+binance: { symbol: BTCUSDT }
+bitstamp: { symbol: BTCUSDT }
+"#,
+        )
+        .unwrap();
+        let registry = ExchangeRegistry::new(&cfg);
+        let max_age = Duration::from_secs(cfg.health.max_update_age_secs);
+        // Valid book but no connected exchange
+        assert!(!serving(true, &registry, max_age));
+
+        registry.set_connection(Exchange::Binance, ConnectionState::Connected);
+        assert!(!serving(true, &registry, max_age));
+        // Trades and BBO updates do not make a book fresh
+        registry.touch(Exchange::Binance);
+        assert!(!serving(true, &registry, max_age));
+        registry.touch_book(Exchange::Binance);
+        assert!(serving(true, &registry, max_age));
+        assert!(!serving(false, &registry, max_age));
+
+        registry.set_connection(Exchange::Binance, ConnectionState::Connecting);
+        assert!(!serving(true, &registry, max_age));
+    }
+}
@@ -0,0 +1,143 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hdrhistogram::Histogram;
+use strum::{EnumCount, IntoEnumIterator};
+use tracing::info;
+
+use crate::server::{LatencyReport, StageLatency};
+use crate::Exchange;
+
+/// Highest trackable latency - one minute
+const MAX_LATENCY_US: u64 = 60_000_000;
+
+/// Microseconds since UNIX epoch
+pub fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or_default()
+}
+
+/// Update pipeline stages
+#[derive(PartialEq, Eq, Debug, Clone, Copy, EnumCount, strum::EnumIter)]
+pub enum Stage {
+    /// Exchange event time -> websocket frame received
+    Network,
+    /// Frame received -> update parsed
+    Parse,
+    /// Update parsed -> merged summary published by the listener
+    Merge,
+    /// Summary published -> sent to gRPC client stream
+    Delivery,
+}
+
+/// Per exchange, per stage latency histograms
+#[derive(Clone)]
+pub struct LatencyRecorder {
+    hist: Arc<Mutex<Vec<Histogram<u64>>>>,
+}
+
+impl Default for LatencyRecorder {
+    fn default() -> Self {
+        let hist = (0..Exchange::COUNT * Stage::COUNT)
+            .map(|_| Histogram::new_with_bounds(1, MAX_LATENCY_US, 3).expect("Valid bounds"))
+            .collect();
+        Self {
+            hist: Arc::new(Mutex::new(hist)),
+        }
+    }
+}
+
+impl LatencyRecorder {
+    /// Records latency between two timestamps (microseconds since epoch).
+    /// Timestamps out of order (e.g. clock skew) are recorded as zero.
+    pub fn record(&self, exchange: Exchange, stage: Stage, from_us: u64, to_us: u64) {
+        let value = to_us.saturating_sub(from_us);
+        let mut hist = self.hist.lock().expect("Latency lock poisoned");
+        hist[Self::idx(exchange, stage)].saturating_record(value);
+    }
+
+    pub fn report(&self) -> LatencyReport {
+        let hist = self.hist.lock().expect("Latency lock poisoned");
+        let mut stages = Vec::with_capacity(hist.len());
+        for exchange in Exchange::iter() {
+            for stage in Stage::iter() {
+                let h = &hist[Self::idx(exchange, stage)];
+                stages.push(StageLatency {
+                    exchange: exchange.name().to_string(),
+                    stage: format!("{:?}", stage),
+                    count: h.len(),
+                    p50_us: h.value_at_quantile(0.5),
+                    p90_us: h.value_at_quantile(0.9),
+                    p99_us: h.value_at_quantile(0.99),
+                    max_us: h.max(),
+                });
+            }
+        }
+        LatencyReport { stages }
+    }
+
+    /// Periodically logs latency percentiles
+    pub async fn run_reporter(&self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        // First tick completes immediately
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            for s in self.report().stages.iter().filter(|s| s.count > 0) {
+                info!(
+                    exchange = %s.exchange,
+                    stage = %s.stage,
+                    count = s.count,
+                    p50_us = s.p50_us,
+                    p99_us = s.p99_us,
+                    max_us = s.max_us,
+                    "Latency"
+                );
+            }
+        }
+    }
+
+    fn idx(exchange: Exchange, stage: Stage) -> usize {
+        exchange as usize * Stage::COUNT + stage as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LatencyRecorder, Stage};
+    use crate::Exchange;
+
+    #[test]
+    fn test_record() {
+        let recorder = LatencyRecorder::default();
+        recorder.record(Exchange::Bitstamp, Stage::Parse, 100, 300);
+        recorder.record(Exchange::Bitstamp, Stage::Parse, 100, 500);
+        // Clock skew
+        recorder.record(Exchange::Bitstamp, Stage::Network, 500, 100);
+
+        let report = recorder.report();
+        let parse = report
+            .stages
+            .iter()
+            .find(|s| s.exchange == "Bitstamp" && s.stage == "Parse")
+            .unwrap();
+        assert_eq!(parse.count, 2);
+        assert_eq!(parse.max_us, 400);
+
+        let network = report
+            .stages
+            .iter()
+            .find(|s| s.exchange == "Bitstamp" && s.stage == "Network")
+            .unwrap();
+        assert_eq!(network.count, 1);
+        assert_eq!(network.max_us, 0);
+
+        assert!(report
+            .stages
+            .iter()
+            .filter(|s| s.exchange == "Binance")
+            .all(|s| s.count == 0));
+    }
+}
@@ -1,12 +1,41 @@
+// tonic::Status is the natural error type of request handling helpers,
+// handshake callback errors are HTTP responses
+#![allow(clippy::result_large_err)]
+
 use serde::{Deserialize, Deserializer};
 use std::sync::atomic::AtomicU64;
-use strum::{EnumCount, EnumIter};
+use strum::{EnumCount, EnumIter, EnumString, IntoStaticStr};
 
+pub mod aggregation;
+pub mod alerts;
+pub mod arbitrage;
+pub mod auth;
+pub mod bars;
+pub mod bbo;
 pub mod binance;
 pub mod bitstamp;
+pub mod book_diff;
 pub mod config;
+pub mod connectors;
 pub mod exchange_listener;
+pub mod fees;
+pub mod fx;
+pub mod gateway;
+pub mod health;
+pub mod latency;
+pub mod logging;
+pub mod metrics;
+pub mod quote;
+pub mod registry;
+pub mod reload;
 pub mod server;
+pub mod sor;
+pub mod subscription;
+#[cfg(test)]
+mod test_util;
+pub mod tls;
+pub mod trades;
+pub mod validation;
 
 #[derive(Debug, Clone)]
 pub enum TrackerError {
@@ -15,18 +44,51 @@ pub enum TrackerError {
     Other(String),
 }
 
+impl std::fmt::Display for TrackerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrackerError::Cnnection(e) | TrackerError::Config(e) | TrackerError::Other(e) => {
+                f.write_str(e)
+            }
+        }
+    }
+}
+
 impl From<String> for TrackerError {
     fn from(s: String) -> Self {
         TrackerError::Other(s)
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Clone, Copy, EnumCount, EnumIter)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, EnumCount, EnumIter, EnumString, IntoStaticStr)]
+#[strum(ascii_case_insensitive)]
 pub enum Exchange {
     Binance,
     Bitstamp,
 }
 
+impl Exchange {
+    pub fn name(self) -> &'static str {
+        self.into()
+    }
+
+    /// Stable proto identifier
+    pub fn id(self) -> server::ExchangeId {
+        match self {
+            Exchange::Binance => server::ExchangeId::Binance,
+            Exchange::Bitstamp => server::ExchangeId::Bitstamp,
+        }
+    }
+
+    pub fn from_id(id: server::ExchangeId) -> Option<Self> {
+        match id {
+            server::ExchangeId::Binance => Some(Exchange::Binance),
+            server::ExchangeId::Bitstamp => Some(Exchange::Bitstamp),
+            server::ExchangeId::ExchangeUnspecified => None,
+        }
+    }
+}
+
 /// Counter to sort by creation order in case of eqality in price and amount
 static ORDER_ID: AtomicU64 = AtomicU64::new(0);
 
@@ -88,12 +150,36 @@ impl From<binance::api::Order> for Order {
     }
 }
 
+/// Update processing timestamps, microseconds since UNIX epoch
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timestamps {
+    /// Event time reported by the exchange, if available
+    pub event: Option<u64>,
+    /// Websocket frame received
+    pub received: u64,
+    /// Frame parsed into order book
+    pub parsed: u64,
+}
+
+/// Conversion of order book prices to the reference currency
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QuoteConversion {
+    /// Exchange quote currency
+    pub quote: String,
+    pub reference: String,
+    /// Reference currency units per quote currency unit
+    pub rate: f64,
+}
+
 /// Generalized order book data
 #[derive(Debug, Clone)]
 pub struct OrderBook {
     pub exchange: Exchange,
     pub bids: Vec<Order>,
     pub asks: Vec<Order>,
+    pub timestamps: Timestamps,
+    /// Set if prices were converted to the reference currency
+    pub conversion: Option<QuoteConversion>,
 }
 
 impl Default for OrderBook {
@@ -102,42 +188,177 @@ impl Default for OrderBook {
             exchange: Exchange::Binance,
             bids: Default::default(),
             asks: Default::default(),
+            timestamps: Default::default(),
+            conversion: None,
+        }
+    }
+}
+
+impl OrderBook {
+    /// Empty book, removes the exchange from the merged book
+    pub fn cleared(exchange: Exchange) -> Self {
+        let now = latency::now_us();
+        Self {
+            exchange,
+            ..Default::default()
         }
+        .with_local_timestamps(now, now)
+    }
+
+    /// Sets local frame receive and parse timestamps
+    pub fn with_local_timestamps(mut self, received: u64, parsed: u64) -> Self {
+        self.timestamps.received = received;
+        self.timestamps.parsed = parsed;
+        self
+    }
+}
+
+impl OrderBook {
+    /// Best bid and offer, `None` unless both sides have orders
+    pub fn bbo(&self) -> Option<Bbo> {
+        let (bid, ask) = (self.bids.first()?, self.asks.first()?);
+        Some(Bbo {
+            exchange: self.exchange,
+            bid_price: bid.price,
+            bid_quantity: bid.quantity,
+            ask_price: ask.price,
+            ask_quantity: ask.quantity,
+            timestamps: self.timestamps,
+        })
     }
 }
 
 impl From<bitstamp::api::OrderBook> for OrderBook {
     fn from(book: bitstamp::api::OrderBook) -> Self {
-        let max_len_bids = std::cmp::min(crate::exchange_listener::MAX_DEPTH, book.bids.len());
-        let max_len_asks = std::cmp::min(crate::exchange_listener::MAX_DEPTH, book.asks.len());
         Self {
             exchange: Exchange::Bitstamp,
-            bids: book.bids[0..max_len_bids]
-                .iter()
-                .map(|el| el.clone().into())
-                .collect(),
-            asks: book.asks[0..max_len_asks]
-                .iter()
-                .map(|el| el.clone().into())
-                .collect(),
+            bids: book.bids.into_iter().map(Order::from).collect(),
+            asks: book.asks.into_iter().map(Order::from).collect(),
+            timestamps: Timestamps {
+                event: book.microtimestamp.parse().ok(),
+                ..Default::default()
+            },
+            conversion: None,
         }
     }
 }
 
 impl From<binance::api::OrderBook> for OrderBook {
     fn from(book: binance::api::OrderBook) -> Self {
-        let max_len_bids = std::cmp::min(crate::exchange_listener::MAX_DEPTH, book.bids.len());
-        let max_len_asks = std::cmp::min(crate::exchange_listener::MAX_DEPTH, book.asks.len());
         Self {
             exchange: Exchange::Binance,
-            bids: book.bids[0..max_len_bids]
-                .iter()
-                .map(|el| el.clone().into())
-                .collect(),
-            asks: book.asks[0..max_len_asks]
-                .iter()
-                .map(|el| el.clone().into())
-                .collect(),
+            bids: book.bids.into_iter().map(Order::from).collect(),
+            asks: book.asks.into_iter().map(Order::from).collect(),
+            // Partial depth stream carries no event time
+            timestamps: Default::default(),
+            conversion: None,
+        }
+    }
+}
+
+/// Generalized best bid and offer
+#[derive(Debug, Clone, Copy)]
+pub struct Bbo {
+    pub exchange: Exchange,
+    pub bid_price: f64,
+    pub bid_quantity: f64,
+    pub ask_price: f64,
+    pub ask_quantity: f64,
+    pub timestamps: Timestamps,
+}
+
+impl Bbo {
+    /// Zero quantities on both sides, removes the exchange from the merged BBO
+    pub fn cleared(exchange: Exchange) -> Self {
+        let now = latency::now_us();
+        Self {
+            exchange,
+            bid_price: 0.0,
+            bid_quantity: 0.0,
+            ask_price: 0.0,
+            ask_quantity: 0.0,
+            timestamps: Timestamps {
+                received: now,
+                parsed: now,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl From<binance::api::BookTicker> for Bbo {
+    fn from(ticker: binance::api::BookTicker) -> Self {
+        Self {
+            exchange: Exchange::Binance,
+            bid_price: ticker.bid_price,
+            bid_quantity: ticker.bid_quantity,
+            ask_price: ticker.ask_price,
+            ask_quantity: ticker.ask_quantity,
+            // Book ticker stream carries no event time
+            timestamps: Default::default(),
+        }
+    }
+}
+
+/// Generalized trade data
+#[derive(Debug, Clone)]
+pub struct Trade {
+    pub exchange: Exchange,
+    /// Exchange trade id
+    pub id: u64,
+    pub price: f64,
+    pub quantity: f64,
+    /// Side of the taker order
+    pub aggressor: server::Side,
+    /// Event is the exchange trade time
+    pub timestamps: Timestamps,
+}
+
+impl Trade {
+    /// Sets local frame receive and parse timestamps
+    pub fn with_local_timestamps(mut self, received: u64, parsed: u64) -> Self {
+        self.timestamps.received = received;
+        self.timestamps.parsed = parsed;
+        self
+    }
+}
+
+impl From<bitstamp::api::Trade> for Trade {
+    fn from(trade: bitstamp::api::Trade) -> Self {
+        Self {
+            exchange: Exchange::Bitstamp,
+            id: trade.id,
+            price: trade.price,
+            quantity: trade.amount,
+            aggressor: if trade.side == 0 {
+                server::Side::Buy
+            } else {
+                server::Side::Sell
+            },
+            timestamps: Timestamps {
+                event: trade.microtimestamp.parse().ok(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl From<binance::api::Trade> for Trade {
+    fn from(trade: binance::api::Trade) -> Self {
+        Self {
+            exchange: Exchange::Binance,
+            id: trade.id,
+            price: trade.price,
+            quantity: trade.quantity,
+            aggressor: if trade.buyer_maker {
+                server::Side::Sell
+            } else {
+                server::Side::Buy
+            },
+            timestamps: Timestamps {
+                event: Some(trade.time * 1000),
+                ..Default::default()
+            },
         }
     }
 }
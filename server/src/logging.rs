@@ -0,0 +1,23 @@
+use tracing_subscriber::EnvFilter;
+
+use crate::{config::LogConfig, TrackerError};
+
+/// Installs global tracing subscriber.
+/// `RUST_LOG` takes precedence over the configured filter.
+pub fn init(cfg: &LogConfig) -> Result<(), TrackerError> {
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(f) => f,
+        Err(_) => EnvFilter::try_new(&cfg.level).map_err(|e| {
+            TrackerError::Config(format!("Invalid log level '{}': {}", cfg.level, e))
+        })?,
+    };
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let res = if cfg.json {
+        builder.json().flatten_event(true).try_init()
+    } else {
+        builder.try_init()
+    };
+
+    res.map_err(|e| TrackerError::Other(format!("Logger init error: {}", e)))
+}
@@ -1,13 +1,37 @@
-use std::path::Path;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use clap::{Arg, Command};
 use exchange_tracker::{
-    binance::BinanceSubscriber,
-    bitstamp::BitstampSubscriber,
+    alerts::AlertEngine,
+    arbitrage::OpportunityDetector,
+    auth::Authenticator,
+    bars::BarBuilder,
+    bbo::BboMerger,
+    config::{ConfigSource, ServerConfig, ENV_CONFIG_PATH},
+    connectors::ConnectorSupervisor,
     exchange_listener::ExchangeListener,
-    server::{orderbook_aggregator_server::OrderbookAggregatorServer, OrderbookServer, Summary},
+    fees::FeeSchedule,
+    fx::{FxNormalizer, FxRates, FxTracker},
+    gateway::{rest::RestGateway, ws::WsGateway},
+    health::HealthMonitor,
+    latency::LatencyRecorder,
+    metrics::MetricsEngine,
+    registry::ExchangeRegistry,
+    reload::ConfigWatcher,
+    server::{
+        orderbook_aggregator_server::OrderbookAggregatorServer, Feeds, OrderbookServer, Summary,
+        FILE_DESCRIPTOR_SET,
+    },
+    subscription::SubscriptionPolicy,
+    trades::TradeTape,
+    validation::BookValidator,
+    TrackerError,
 };
 use tokio::sync::oneshot;
+use tonic::service::interceptor::InterceptedService;
+use tracing::{error, info};
 
 #[tokio::main]
 async fn main() {
@@ -17,71 +41,314 @@ async fn main() {
         .version(version)
         .arg(
             Arg::new("config_path")
-                .help("Path to the YAML configuration file")
+                .help("Path to the YAML configuration file, EXCHANGE_TRACKER_CONFIG if not set")
                 .short('c')
                 .takes_value(true)
-                .required(true),
+                .global(true),
+        )
+        .arg(
+            Arg::new("set")
+                .help("Setting override, e.g. binance.symbol=ETHUSDT, applied after EXCHANGE_TRACKER_* environment variables")
+                .short('s')
+                .long("set")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .global(true),
+        )
+        .subcommand(
+            Command::new("validate-config")
+                .about("Check the configuration without connecting anywhere and exit"),
         )
         .get_matches();
 
-    let path_str = matches.value_of("config_path").unwrap();
-    let path = Path::new(path_str);
-    if !path.exists() {
-        panic!(
-            "Provided configuration file path does not exist: {}",
-            path_str
-        );
+    // Exit codes: 1 for an invalid configuration, 2 for usage errors
+    let path = match matches
+        .value_of("config_path")
+        .map(String::from)
+        .or_else(|| std::env::var(ENV_CONFIG_PATH).ok())
+    {
+        Some(path) => PathBuf::from(path),
+        None => {
+            eprintln!(
+                "Configuration file not set, use -c <path> or {}",
+                ENV_CONFIG_PATH
+            );
+            std::process::exit(2);
+        }
+    };
+    let overrides = matches
+        .values_of("set")
+        .map(|v| v.map(String::from).collect())
+        .unwrap_or_default();
+    let source = ConfigSource::new(&path, overrides);
+    let config = match source.load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let problems = config.validate();
+    if !problems.is_empty() {
+        eprintln!("Invalid configuration {}:", path.display());
+        for problem in &problems {
+            eprintln!("  {}", problem);
+        }
+        std::process::exit(1);
+    }
+    if matches.subcommand_matches("validate-config").is_some() {
+        println!("Configuration {} is valid", path.display());
+        return;
+    }
+
+    if let Err(e) = run(source, config).await {
+        eprintln!("{}", e);
+        std::process::exit(1);
     }
+}
 
-    let str = std::fs::read_to_string(path).expect("Failed to read configuration file");
-    let config: exchange_tracker::config::ServerConfig =
-        serde_yaml::from_str(str.as_str()).expect("Failed to deserialize configuration file");
+/// Runs the tracker until a component fails
+async fn run(source: ConfigSource, config: ServerConfig) -> Result<(), TrackerError> {
+    exchange_tracker::logging::init(&config.log)?;
 
     let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
     let (merged_tx, merged_rx) = tokio::sync::watch::channel(Summary::default());
 
-    let mut binance = BinanceSubscriber::new(config.binance.clone(), tx.clone()).unwrap();
-    let mut bitstamp = BitstampSubscriber::new(config.bitstamp.clone(), tx).unwrap();
-    let mut listener = ExchangeListener::new(rx, merged_tx);
+    let (trades_tx, trades_rx) = tokio::sync::mpsc::unbounded_channel();
+    let (bbo_tx, bbo_rx) = tokio::sync::mpsc::unbounded_channel();
+
+    // Live changes of the configuration file, see `reload::apply`
+    let watcher = ConfigWatcher::new(source, config.clone());
+    let live_config = watcher.subscribe();
+    tokio::spawn(watcher.run());
+
+    let registry = ExchangeRegistry::new(&config);
+    let connectors =
+        ConnectorSupervisor::new(live_config.clone(), tx, trades_tx, bbo_tx, registry.clone());
+
+    // Quote currency normalization of books, trades and BBOs
+    let (rx, trades_rx, bbo_rx) = if config.fx.reference.is_empty() {
+        (rx, trades_rx, bbo_rx)
+    } else {
+        let rates = FxRates::new(&config.fx)?;
+        tokio::spawn(FxTracker::new(&config.fx, rates.clone(), config.binance.ws_url()).run());
+        let (fx_tx, fx_rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(FxNormalizer::new(rx, fx_tx, rates.clone(), registry.clone()).run());
+        let (fx_trades_tx, fx_trades_rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(
+            FxNormalizer::new(trades_rx, fx_trades_tx, rates.clone(), registry.clone()).run(),
+        );
+        let (fx_bbo_tx, fx_bbo_rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(FxNormalizer::new(bbo_rx, fx_bbo_tx, rates, registry.clone()).run());
+        (fx_rx, fx_trades_rx, fx_bbo_rx)
+    };
+
+    // Bad tick filtering, after conversion so venues compare in one currency
+    let (rx, bbo_rx) = if config.validation.enabled {
+        let (valid_tx, valid_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (valid_bbo_tx, valid_bbo_rx) = tokio::sync::mpsc::unbounded_channel();
+        let validator = BookValidator::new(
+            live_config.clone(),
+            rx,
+            valid_tx,
+            bbo_rx,
+            valid_bbo_tx,
+            registry.clone(),
+        );
+        tokio::spawn(validator.run());
+        (valid_rx, valid_bbo_rx)
+    } else {
+        (rx, bbo_rx)
+    };
+
+    let (trade_feed_tx, _) = tokio::sync::broadcast::channel(1024);
+    let tape = TradeTape::new(&config.trades, trades_rx, trade_feed_tx.clone());
+    let trade_stats = tape.subscribe_stats();
+    tokio::spawn(tape.run());
+
+    let bbo = BboMerger::new(bbo_rx);
+    let bbo_venues = bbo.subscribe();
+    tokio::spawn(bbo.run());
+
+    let latency = LatencyRecorder::default();
+    let fees = FeeSchedule::new(&config);
+    let mut listener = ExchangeListener::new(
+        rx,
+        merged_tx,
+        latency.clone(),
+        config.merge.fee_adjusted.then(|| fees.clone()),
+        trade_stats,
+    );
+
+    let (opportunities_tx, _) = tokio::sync::broadcast::channel(1024);
+    let detector = OpportunityDetector::new(
+        merged_rx.clone(),
+        opportunities_tx.clone(),
+        fees,
+        &config.arbitrage,
+    );
+    tokio::spawn(detector.run());
+
+    let metrics = MetricsEngine::new(
+        &config.metrics,
+        merged_rx.clone(),
+        listener.subscribe_books(),
+    );
+    let metrics_rx = metrics.subscribe();
+    tokio::spawn(metrics.run());
+
+    let alerts = AlertEngine::new(
+        live_config.clone(),
+        merged_rx.clone(),
+        listener.subscribe_books(),
+        registry.clone(),
+    )?;
+    tokio::spawn(alerts.run());
+
+    let bars = BarBuilder::new(&config.bars, merged_rx.clone());
+    let bar_store = bars.store();
+    tokio::spawn(bars.run());
 
     let (_tx, shutdown_rx_handle) = oneshot::channel::<()>();
-    let grpc_srv = OrderbookAggregatorServer::new(OrderbookServer::new(merged_rx));
+    let auth = Authenticator::new(config.auth.as_ref());
+    tokio::spawn(auth.clone().run(live_config.clone()));
+
+    if let Some(addr) = &config.ws_listen_addr {
+        let addr: SocketAddr = addr.parse().map_err(|e| {
+            TrackerError::Config(format!("Invalid WebSocket address {}: {}", addr, e))
+        })?;
+        let gateway = WsGateway::new(
+            merged_rx.clone(),
+            listener.subscribe_books(),
+            auth.clone(),
+            SubscriptionPolicy::new(live_config.clone()),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = gateway.run(addr).await {
+                error!(error = ?e, "WebSocket gateway failed");
+            }
+        });
+    }
+
+    let feeds = Feeds {
+        summary: merged_rx,
+        books: listener.subscribe_books(),
+        metrics: metrics_rx,
+        opportunities: opportunities_tx,
+        bars: bar_store,
+        trades: trade_feed_tx,
+        bbo: bbo_venues,
+    };
+    let health_monitor = HealthMonitor::new(
+        live_config.clone(),
+        listener.subscribe_valid(),
+        registry.clone(),
+    );
+    let orderbook_srv = Arc::new(OrderbookServer::new(
+        feeds,
+        latency.clone(),
+        registry,
+        live_config.clone(),
+    ));
+
+    if let Some(addr) = &config.http_listen_addr {
+        let addr: SocketAddr = addr
+            .parse()
+            .map_err(|e| TrackerError::Config(format!("Invalid HTTP address {}: {}", addr, e)))?;
+        let gateway = RestGateway::new(
+            orderbook_srv.clone(),
+            auth.clone(),
+            health_monitor.subscribe(),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = gateway.run(addr).await {
+                error!(error = ?e, "REST gateway failed");
+            }
+        });
+    }
+
+    // gRPC-Web enabled for browser clients, requires HTTP/1.1
+    let grpc_srv = tonic_web::enable(InterceptedService::new(
+        OrderbookAggregatorServer::from_arc(orderbook_srv),
+        auth,
+    ));
+
+    let (health_reporter, health_srv) = tonic_health::server::health_reporter();
+    tokio::spawn(exchange_tracker::health::run(
+        health_reporter,
+        health_monitor.subscribe(),
+    ));
+    tokio::spawn(health_monitor.run());
+
+    let reflection_srv = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        .build()
+        .map_err(|e| TrackerError::Other(format!("Failed to build reflection service: {}", e)))?;
+
+    let report_interval = config.latency.report_interval_secs;
+    if report_interval > 0 {
+        tokio::spawn(async move {
+            latency
+                .run_reporter(std::time::Duration::from_secs(report_interval))
+                .await
+        });
+    }
+
+    let grpc_addr: SocketAddr = config.grpc_listen_addr.parse().map_err(|e| {
+        TrackerError::Config(format!(
+            "Invalid gRPC address {}: {}",
+            config.grpc_listen_addr, e
+        ))
+    })?;
+    let mut grpc_builder = tonic::transport::Server::builder().accept_http1(true);
+    if let Some(tls) = &config.tls {
+        let tls_config = exchange_tracker::tls::server_tls_config(tls)?;
+        grpc_builder = grpc_builder
+            .tls_config(tls_config)
+            .map_err(|e| TrackerError::Config(format!("Invalid TLS configuration: {}", e)))?;
+    }
+    info!(
+        addr = %config.grpc_listen_addr,
+        tls = config.tls.is_some(),
+        mtls = config.tls.as_ref().map(|t| t.client_ca_path.is_some()).unwrap_or(false),
+        "Starting gRPC server"
+    );
 
     let grpc_future = tokio::spawn(async move {
-        tonic::transport::Server::builder()
+        grpc_builder
+            .add_service(health_srv)
+            .add_service(reflection_srv)
             .add_service(grpc_srv)
-            .serve_with_shutdown(
-                config.grpc_listen_addr.parse().expect("Invalid grpc url"),
-                async move {
-                    let _ = shutdown_rx_handle.await;
-                },
-            )
+            .serve_with_shutdown(grpc_addr, async move {
+                let _ = shutdown_rx_handle.await;
+            })
             .await
-            .expect("Failed to start grpc server");
     });
 
     tokio::select! {
         r = grpc_future => {
-            if let Err(r) = r {
-                println!("{:?}", r);
+            let r = r
+                .map_err(|e| TrackerError::Other(format!("gRPC server task failed: {}", e)))?;
+            if let Err(e) = r {
+                error!(error = ?e, "gRPC server failed");
+                return Err(TrackerError::Cnnection(format!("gRPC server failed: {}", e)));
             }
         },
         r = listener.run() => {
-            if let Err(r) = r {
-                println!("{:?}", r);
-            }
-        },
-        r = binance.run() => {
-            if let Err(r) = r {
-                println!("{:?}", r);
+            if let Err(e) = r {
+                error!(error = ?e, "Listener failed");
+                return Err(e);
             }
         },
-        r = bitstamp.run() => {
-            if let Err(r) = r {
-                println!("{:?}", r);
+        r = connectors.run() => {
+            if let Err(e) = r {
+                error!(error = ?e, "Exchange connector failed");
+                return Err(e);
             }
         }
     }
 
-    println!("End");
+    info!("End");
+    Ok(())
 }
@@ -0,0 +1,350 @@
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+
+use strum::{EnumCount, IntoEnumIterator};
+use tokio::sync::watch;
+
+use crate::config::MetricsConfig;
+use crate::latency::now_us;
+use crate::server::{DepthBand, Level, MarketMetrics, Summary, VenueShare, WindowMetrics};
+use crate::{Exchange, OrderBook};
+
+/// Windowed values: mid, spread, imbalance, then best bid and best ask flags per exchange
+type Sample = Vec<f64>;
+const MID: usize = 0;
+const SPREAD: usize = 1;
+const IMBALANCE: usize = 2;
+const BEST_BID: usize = 3;
+const BEST_ASK: usize = BEST_BID + Exchange::COUNT;
+const SAMPLE_LEN: usize = BEST_ASK + Exchange::COUNT;
+
+/// Time weighted sums of samples over a sliding window
+struct Window {
+    secs: u32,
+    /// Start, end (microseconds) and value of samples in the window
+    segments: VecDeque<(u64, u64, Sample)>,
+    sums: Vec<f64>,
+    duration_us: u64,
+}
+
+impl Window {
+    fn new(secs: u32) -> Self {
+        Self {
+            secs,
+            segments: VecDeque::new(),
+            sums: vec![0.0; SAMPLE_LEN],
+            duration_us: 0,
+        }
+    }
+
+    fn add(&mut self, start: u64, end: u64, sample: Sample, sign: f64) {
+        let d = end.saturating_sub(start);
+        for (sum, v) in self.sums.iter_mut().zip(&sample) {
+            *sum += sign * v * d as f64;
+        }
+        if sign > 0.0 {
+            self.duration_us += d;
+            self.segments.push_back((start, end, sample));
+        } else {
+            self.duration_us -= d;
+        }
+    }
+
+    /// Adds sample valid from `start` to `end` and drops samples older than the window
+    fn push(&mut self, start: u64, end: u64, sample: Sample) {
+        self.add(start, end, sample, 1.0);
+        let from = end.saturating_sub(self.secs as u64 * 1_000_000);
+        while self.segments.front().map(|s| s.1 <= from).unwrap_or(false) {
+            if let Some((s, e, sample)) = self.segments.pop_front() {
+                self.add(s, e, sample, -1.0);
+            }
+        }
+    }
+
+    fn metrics(&self) -> WindowMetrics {
+        if self.duration_us == 0 {
+            return WindowMetrics {
+                window_secs: self.secs,
+                ..Default::default()
+            };
+        }
+        let avg = |i: usize| self.sums[i] / self.duration_us as f64;
+        WindowMetrics {
+            window_secs: self.secs,
+            mid: avg(MID),
+            spread: avg(SPREAD),
+            imbalance: avg(IMBALANCE),
+            best_time_share: Exchange::iter()
+                .map(|e| VenueShare {
+                    exchange_id: e.id() as i32,
+                    bid_share: avg(BEST_BID + e as usize),
+                    ask_share: avg(BEST_ASK + e as usize),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Computes market metrics on every merged book update
+pub struct MetricsEngine {
+    cfg: MetricsConfig,
+    summary: watch::Receiver<Summary>,
+    books: watch::Receiver<Vec<OrderBook>>,
+    tx: watch::Sender<MarketMetrics>,
+    windows: Vec<Window>,
+    /// Latest sample, its window segment ends with the next update
+    last: Option<(u64, Sample)>,
+}
+
+impl MetricsEngine {
+    pub fn new(
+        cfg: &MetricsConfig,
+        summary: watch::Receiver<Summary>,
+        books: watch::Receiver<Vec<OrderBook>>,
+    ) -> Self {
+        Self {
+            cfg: cfg.clone(),
+            summary,
+            books,
+            tx: watch::channel(MarketMetrics::default()).0,
+            windows: cfg.windows_secs.iter().map(|&s| Window::new(s)).collect(),
+            last: None,
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<MarketMetrics> {
+        self.tx.subscribe()
+    }
+
+    pub async fn run(mut self) {
+        while self.summary.changed().await.is_ok() {
+            let metrics = compute(
+                &self.cfg,
+                &self.summary.borrow_and_update(),
+                &self.books.borrow(),
+            );
+            if let Some(m) = metrics {
+                self.publish(m, now_us());
+            }
+        }
+        // Listener dropped - app is shutting down
+    }
+
+    fn publish(&mut self, mut metrics: MarketMetrics, now: u64) {
+        let mut sample = vec![0.0; SAMPLE_LEN];
+        sample[MID] = metrics.mid;
+        sample[SPREAD] = metrics.spread;
+        sample[IMBALANCE] = metrics.imbalance;
+        for (i, share) in metrics.top_of_book.iter().enumerate() {
+            sample[BEST_BID + i] = if share.bid_share > 0.0 { 1.0 } else { 0.0 };
+            sample[BEST_ASK + i] = if share.ask_share > 0.0 { 1.0 } else { 0.0 };
+        }
+        if let Some((start, prev)) = self.last.take() {
+            for w in self.windows.iter_mut() {
+                w.push(start, now, prev.clone());
+            }
+        }
+        self.last = Some((now, sample));
+
+        metrics.timestamp_us = now;
+        metrics.windows = self.windows.iter().map(Window::metrics).collect();
+        self.tx.send_replace(metrics);
+    }
+}
+
+/// `n / d`, or `zero` if there is no amount to divide by
+fn ratio(n: f64, d: f64, zero: f64) -> f64 {
+    if d > 0.0 {
+        n / d
+    } else {
+        zero
+    }
+}
+
+/// Instantaneous metrics, `None` unless both sides have levels
+fn compute(cfg: &MetricsConfig, summary: &Summary, books: &[OrderBook]) -> Option<MarketMetrics> {
+    // Merged book may be fee adjusted, metrics use exchange quoted prices
+    let sorted = |levels: &[Level], bid: bool| {
+        let mut levels: Vec<(f64, f64, i32)> = levels
+            .iter()
+            .map(|l| (l.raw_price, l.amount, l.exchange_id))
+            .collect();
+        levels.sort_by(|a, b| {
+            let ord = a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal);
+            if bid {
+                ord.reverse()
+            } else {
+                ord
+            }
+        });
+        levels
+    };
+    let bids = sorted(&summary.bids, true);
+    let asks = sorted(&summary.asks, false);
+    let (best_bid, best_ask) = (bids.first()?.0, asks.first()?.0);
+    let mid = (best_bid + best_ask) / 2.0;
+
+    let top = |levels: &[(f64, f64, i32)]| {
+        let (notional, amount) = levels
+            .iter()
+            .take(cfg.top_levels.max(1))
+            .fold((0.0, 0.0), |(n, a), l| (n + l.0 * l.1, a + l.1));
+        // Best price if levels have no amount
+        (ratio(notional, amount, levels[0].0), amount)
+    };
+    let (bid_vwap, bid_amount) = top(&bids);
+    let (ask_vwap, ask_amount) = top(&asks);
+
+    // Amount at best price, total and per exchange
+    let at_best = |levels: &[(f64, f64, i32)], exchange: Option<Exchange>| -> f64 {
+        levels
+            .iter()
+            .take_while(|l| l.0 == levels[0].0)
+            .filter(|l| exchange.map(|e| l.2 == e.id() as i32).unwrap_or(true))
+            .map(|l| l.1)
+            .sum()
+    };
+    let (best_bid_amount, best_ask_amount) = (at_best(&bids, None), at_best(&asks, None));
+
+    let depth = cfg
+        .depth_bps
+        .iter()
+        .map(|&bps| {
+            let (low, high) = (mid * (1.0 - bps / 10_000.0), mid * (1.0 + bps / 10_000.0));
+            DepthBand {
+                bps,
+                bid_amount: books
+                    .iter()
+                    .flat_map(|b| b.bids.iter().take_while(|o| o.price >= low))
+                    .map(|o| o.quantity)
+                    .sum(),
+                ask_amount: books
+                    .iter()
+                    .flat_map(|b| b.asks.iter().take_while(|o| o.price <= high))
+                    .map(|o| o.quantity)
+                    .sum(),
+            }
+        })
+        .collect();
+
+    Some(MarketMetrics {
+        mid,
+        weighted_mid: (bid_vwap + ask_vwap) / 2.0,
+        microprice: ratio(
+            best_bid * best_ask_amount + best_ask * best_bid_amount,
+            best_bid_amount + best_ask_amount,
+            mid,
+        ),
+        spread: best_ask - best_bid,
+        imbalance: ratio(bid_amount - ask_amount, bid_amount + ask_amount, 0.0),
+        top_levels: cfg.top_levels as u32,
+        depth,
+        top_of_book: Exchange::iter()
+            .map(|e| VenueShare {
+                exchange_id: e.id() as i32,
+                bid_share: ratio(at_best(&bids, Some(e)), best_bid_amount, 0.0),
+                ask_share: ratio(at_best(&asks, Some(e)), best_ask_amount, 0.0),
+            })
+            .collect(),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compute, Window, BEST_BID, MID, SAMPLE_LEN};
+    use crate::config::MetricsConfig;
+    use crate::server::{ExchangeId, Summary};
+    use crate::test_util::level;
+    use crate::{Exchange, Order, OrderBook};
+
+    fn summary() -> Summary {
+        Summary {
+            bids: vec![
+                level(ExchangeId::Binance, 99.0, 1.0),
+                level(ExchangeId::Bitstamp, 99.0, 3.0),
+                level(ExchangeId::Binance, 98.0, 4.0),
+            ],
+            asks: vec![level(ExchangeId::Bitstamp, 101.0, 2.0)],
+            ..Default::default()
+        }
+    }
+
+    fn config() -> MetricsConfig {
+        MetricsConfig {
+            top_levels: 2,
+            depth_bps: vec![100.0, 200.0],
+            windows_secs: vec![],
+        }
+    }
+
+    #[test]
+    fn test_compute() {
+        let order = |price, quantity| Order {
+            price,
+            quantity,
+            id: 0,
+            exchange: Exchange::Binance,
+        };
+        let books = vec![OrderBook {
+            bids: vec![order(99.0, 1.0), order(98.0, 4.0)],
+            asks: vec![order(101.0, 1.0)],
+            ..Default::default()
+        }];
+
+        let m = compute(&config(), &summary(), &books).unwrap();
+        assert_eq!(m.mid, 100.0);
+        assert_eq!(m.spread, 2.0);
+        // Top 2 bids 4.0 @ 99, ask 2.0 @ 101
+        assert!((m.imbalance - 2.0 / 6.0).abs() < 1e-9);
+        assert_eq!(m.weighted_mid, 100.0);
+        assert!((m.microprice - (99.0 * 2.0 + 101.0 * 4.0) / 6.0).abs() < 1e-9);
+        assert_eq!(m.depth[0].bid_amount, 1.0);
+        assert_eq!(m.depth[0].ask_amount, 1.0);
+        assert_eq!(m.depth[1].bid_amount, 5.0);
+    }
+
+    #[test]
+    fn test_venue_share() {
+        let m = compute(&config(), &summary(), &[]).unwrap();
+        // Best bid 1.0 on Binance and 3.0 on Bitstamp, best ask on Bitstamp only
+        assert_eq!(m.top_of_book[0].bid_share, 0.25);
+        assert_eq!(m.top_of_book[0].ask_share, 0.0);
+        assert_eq!(m.top_of_book[1].bid_share, 0.75);
+        assert_eq!(m.top_of_book[1].ask_share, 1.0);
+    }
+
+    #[test]
+    fn test_zero_amount_levels() {
+        let summary = Summary {
+            bids: vec![level(ExchangeId::Binance, 99.0, 0.0)],
+            asks: vec![level(ExchangeId::Bitstamp, 101.0, 0.0)],
+            ..Default::default()
+        };
+        let m = compute(&config(), &summary, &[]).unwrap();
+        assert_eq!(m.mid, 100.0);
+        assert_eq!(m.weighted_mid, 100.0);
+        assert_eq!(m.microprice, 100.0);
+        assert_eq!(m.imbalance, 0.0);
+        assert_eq!(m.top_of_book[0].bid_share, 0.0);
+        assert_eq!(m.top_of_book[1].ask_share, 0.0);
+    }
+
+    #[test]
+    fn test_window_expiry() {
+        let mut window = Window::new(10);
+        let mut sample = vec![0.0; SAMPLE_LEN];
+        sample[MID] = 100.0;
+        sample[BEST_BID] = 1.0;
+        window.push(0, 4_000_000, sample.clone());
+        sample[MID] = 110.0;
+        sample[BEST_BID] = 0.0;
+        window.push(4_000_000, 8_000_000, sample.clone());
+        let w = window.metrics();
+        assert_eq!(w.mid, 105.0);
+        assert_eq!(w.best_time_share[0].bid_share, 0.5);
+        // First sample falls out of the window
+        window.push(8_000_000, 16_000_000, sample);
+        assert_eq!(window.metrics().mid, 110.0);
+    }
+}
@@ -0,0 +1,194 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+
+use crate::fees::FeeSchedule;
+use crate::server::{ExchangeId, Fill, Level, QuoteRequest, QuoteResponse, Side};
+use crate::Exchange;
+
+/// Liquidity available to a quote
+#[derive(Debug, Clone, Copy)]
+pub struct Liquidity {
+    pub exchange: Exchange,
+    /// Exchange quoted price
+    pub price: f64,
+    pub amount: f64,
+}
+
+impl Liquidity {
+    /// Summary levels with known exchange
+    pub fn from_levels(levels: &[Level]) -> Vec<Self> {
+        levels
+            .iter()
+            .filter_map(|l| {
+                let exchange = ExchangeId::try_from(l.exchange_id)
+                    .ok()
+                    .and_then(Exchange::from_id)?;
+                Some(Self {
+                    exchange,
+                    price: l.raw_price,
+                    amount: l.amount,
+                })
+            })
+            .collect()
+    }
+
+    pub fn from_orders(orders: &[crate::Order]) -> impl Iterator<Item = Self> + '_ {
+        orders.iter().map(|o| Self {
+            exchange: o.exchange,
+            price: o.price,
+            amount: o.quantity,
+        })
+    }
+}
+
+/// Walks the taken side best price first until requested amount is filled
+pub fn quote(
+    req: &QuoteRequest,
+    bids: Vec<Liquidity>,
+    asks: Vec<Liquidity>,
+    fees: Option<&FeeSchedule>,
+) -> Result<QuoteResponse, tonic::Status> {
+    let side = Side::try_from(req.side)
+        .map_err(|_| tonic::Status::invalid_argument(format!("Unknown side {}", req.side)))?;
+    let valid = |v: f64| v.is_finite() && v > 0.0;
+    let (by_quantity, target) = match (valid(req.quantity), valid(req.notional)) {
+        (true, _) => (true, req.quantity),
+        (false, true) if req.quantity == 0.0 => (false, req.notional),
+        _ => {
+            return Err(tonic::Status::invalid_argument(
+                "Positive quantity or notional required",
+            ))
+        }
+    };
+
+    let best_bid = bids.iter().map(|l| l.price).fold(f64::NAN, f64::max);
+    let best_ask = asks.iter().map(|l| l.price).fold(f64::NAN, f64::min);
+    let mid = (best_bid + best_ask) / 2.0;
+
+    let buy = side == Side::Buy;
+    let effective = |l: &Liquidity| {
+        fees.map(|f| f.taker_price(l.exchange, l.price, !buy))
+            .unwrap_or(l.price)
+    };
+    let mut levels: Vec<(f64, Liquidity)> = if buy { asks } else { bids }
+        .into_iter()
+        .map(|l| (effective(&l), l))
+        .collect();
+    levels.sort_by(|a, b| {
+        let ord = a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal);
+        if buy {
+            ord
+        } else {
+            ord.reverse()
+        }
+    });
+
+    let mut resp = QuoteResponse {
+        fees_included: fees.is_some(),
+        mid: if mid.is_nan() { 0.0 } else { mid },
+        ..Default::default()
+    };
+    let mut remaining = target;
+    for (price, level) in levels {
+        let quantity = if by_quantity {
+            remaining.min(level.amount)
+        } else {
+            (remaining / price).min(level.amount)
+        };
+        if quantity <= 0.0 {
+            break;
+        }
+        let notional = quantity * price;
+        let fee = (notional - quantity * level.price).abs();
+        remaining -= if by_quantity { quantity } else { notional };
+
+        resp.quantity += quantity;
+        resp.notional += notional;
+        resp.fees += fee;
+        resp.worst_price = level.price;
+        let exchange_id = level.exchange.id() as i32;
+        let fill = match resp.fills.iter_mut().find(|f| f.exchange_id == exchange_id) {
+            Some(fill) => fill,
+            None => {
+                resp.fills.push(Fill {
+                    exchange_id,
+                    ..Default::default()
+                });
+                resp.fills.last_mut().expect("Fill just added")
+            }
+        };
+        fill.quantity += quantity;
+        fill.notional += notional;
+        fill.fee += fee;
+        fill.average_price = fill.notional / fill.quantity;
+
+        if remaining <= target * 1e-12 {
+            break;
+        }
+    }
+
+    resp.partial = remaining > target * 1e-12;
+    if resp.quantity > 0.0 {
+        resp.vwap = resp.notional / resp.quantity;
+        if resp.mid > 0.0 {
+            let cost = if buy {
+                resp.vwap - resp.mid
+            } else {
+                resp.mid - resp.vwap
+            };
+            resp.slippage_bps = cost / resp.mid * 10_000.0;
+        }
+    }
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quote, Liquidity};
+    use crate::server::{QuoteRequest, Side};
+    use crate::Exchange;
+
+    fn liq(exchange: Exchange, price: f64, amount: f64) -> Liquidity {
+        Liquidity {
+            exchange,
+            price,
+            amount,
+        }
+    }
+
+    #[test]
+    fn test_quote() {
+        let bids = vec![liq(Exchange::Binance, 99.0, 1.0)];
+        let asks = vec![
+            liq(Exchange::Bitstamp, 102.0, 5.0),
+            liq(Exchange::Binance, 101.0, 1.0),
+        ];
+        let req = QuoteRequest {
+            side: Side::Buy as i32,
+            quantity: 3.0,
+            ..Default::default()
+        };
+
+        let resp = quote(&req, bids.clone(), asks.clone(), None).unwrap();
+        assert!(!resp.partial);
+        assert_eq!(resp.quantity, 3.0);
+        assert_eq!(resp.worst_price, 102.0);
+        assert!((resp.vwap - 305.0 / 3.0).abs() < 1e-9);
+        assert_eq!(resp.mid, 100.0);
+        assert!((resp.slippage_bps - (305.0 / 3.0 - 100.0) * 100.0).abs() < 1e-6);
+        assert_eq!(resp.fills.len(), 2);
+        assert_eq!(resp.fills[0].quantity, 1.0);
+        assert_eq!(resp.fills[1].quantity, 2.0);
+
+        let req = QuoteRequest {
+            side: Side::Sell as i32,
+            notional: 198.0,
+            ..Default::default()
+        };
+        let resp = quote(&req, bids, asks, None).unwrap();
+        assert!(resp.partial);
+        assert_eq!(resp.quantity, 1.0);
+
+        assert!(quote(&QuoteRequest::default(), vec![], vec![], None).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+use std::sync::{Arc, RwLock};
+
+use strum::{EnumCount, IntoEnumIterator};
+
+use crate::config::ServerConfig;
+use crate::latency::now_us;
+use crate::server::{BookRejections, ConnectionState, ExchangeInfo, InstrumentInfo};
+use crate::validation::Rejection;
+use crate::Exchange;
+
+/// Trading rules of the tracked symbol, downloaded by connectors
+#[derive(Debug, Clone, Default)]
+pub struct SymbolRules {
+    pub base: String,
+    pub quote: String,
+    pub tick_size: f64,
+    pub lot_size: f64,
+    pub min_quantity: f64,
+    pub min_notional: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ExchangeState {
+    symbol: String,
+    fee_tier: String,
+    connection: ConnectionState,
+    rules: SymbolRules,
+    /// Last book update
+    last_book_us: u64,
+    /// Last message of any feed, trades and BBO included
+    last_message_us: u64,
+    rejections: BookRejections,
+}
+
+/// Connection state and instrument metadata of all exchanges,
+/// updated by connectors and read by the gRPC service
+#[derive(Clone)]
+pub struct ExchangeRegistry {
+    instrument: Arc<RwLock<String>>,
+    state: Arc<RwLock<Vec<ExchangeState>>>,
+}
+
+impl ExchangeRegistry {
+    pub fn new(cfg: &ServerConfig) -> Self {
+        let state = ExchangeState {
+            connection: ConnectionState::Disconnected,
+            ..Default::default()
+        };
+        let registry = Self {
+            instrument: Default::default(),
+            state: Arc::new(RwLock::new(vec![state; Exchange::COUNT])),
+        };
+        registry.configure(cfg);
+        registry
+    }
+
+    /// Applies instrument, symbols and fee tiers of reloaded configuration,
+    /// trading rules of a changed symbol are cleared until downloaded
+    pub fn configure(&self, cfg: &ServerConfig) {
+        *self.instrument.write().expect("Registry lock poisoned") = cfg.instrument().to_string();
+        for exchange in Exchange::iter() {
+            let (symbol, fee_tier) = match exchange {
+                Exchange::Binance => (&cfg.binance.symbol, &cfg.binance.fee_tier),
+                Exchange::Bitstamp => (&cfg.bitstamp.symbol, &cfg.bitstamp.fee_tier),
+            };
+            self.update(exchange, |s| {
+                if s.symbol != *symbol {
+                    s.symbol = symbol.clone();
+                    s.rules = SymbolRules::default();
+                }
+                s.fee_tier = fee_tier.clone();
+            });
+        }
+    }
+
+    fn update<F: FnOnce(&mut ExchangeState)>(&self, exchange: Exchange, f: F) {
+        let mut state = self.state.write().expect("Registry lock poisoned");
+        f(&mut state[exchange as usize]);
+    }
+
+    pub fn set_connection(&self, exchange: Exchange, connection: ConnectionState) {
+        self.update(exchange, |s| s.connection = connection);
+    }
+
+    pub fn set_rules(&self, exchange: Exchange, rules: SymbolRules) {
+        self.update(exchange, |s| s.rules = rules);
+    }
+
+    /// Marks trade or BBO message received now
+    pub fn touch(&self, exchange: Exchange) {
+        self.update(exchange, |s| s.last_message_us = now_us());
+    }
+
+    /// Marks book update received now, staleness only counts book updates
+    pub fn touch_book(&self, exchange: Exchange) {
+        let now = now_us();
+        self.update(exchange, |s| {
+            s.last_book_us = now;
+            s.last_message_us = now;
+        });
+    }
+
+    /// Counts a book rejected by validation
+    pub fn reject(&self, exchange: Exchange, reason: Rejection) {
+        self.update(exchange, |s| reason.count(&mut s.rejections));
+    }
+
+    /// Whether any exchange is connected with a book update within `max_age_us`
+    pub fn live(&self, max_age_us: u64) -> bool {
+        let state = self.state.read().expect("Registry lock poisoned");
+        let now = now_us();
+        state.iter().any(|s| {
+            s.connection == ConnectionState::Connected
+                && s.last_book_us > 0
+                && now.saturating_sub(s.last_book_us) <= max_age_us
+        })
+    }
+
+    pub fn rules(&self, exchange: Exchange) -> SymbolRules {
+        let state = self.state.read().expect("Registry lock poisoned");
+        state[exchange as usize].rules.clone()
+    }
+
+    pub fn exchanges(&self) -> Vec<ExchangeInfo> {
+        let state = self.state.read().expect("Registry lock poisoned");
+        Exchange::iter()
+            .map(|e| {
+                let s = &state[e as usize];
+                ExchangeInfo {
+                    id: e.id() as i32,
+                    name: e.name().to_string(),
+                    state: s.connection as i32,
+                    fee_tier: s.fee_tier.clone(),
+                    last_update_us: s.last_book_us,
+                    last_message_us: s.last_message_us,
+                    rejections: Some(s.rejections.clone()),
+                }
+            })
+            .collect()
+    }
+
+    pub fn instruments(&self) -> Vec<InstrumentInfo> {
+        let instrument = self.instrument.read().expect("Registry lock poisoned");
+        let state = self.state.read().expect("Registry lock poisoned");
+        Exchange::iter()
+            .map(|e| {
+                let s = &state[e as usize];
+                InstrumentInfo {
+                    instrument: instrument.clone(),
+                    exchange_id: e.id() as i32,
+                    native_symbol: s.symbol.clone(),
+                    base: s.rules.base.clone(),
+                    quote: s.rules.quote.clone(),
+                    tick_size: s.rules.tick_size,
+                    lot_size: s.rules.lot_size,
+                    min_quantity: s.rules.min_quantity,
+                    min_notional: s.rules.min_notional,
+                    fee_tier: s.fee_tier.clone(),
+                    state: s.connection as i32,
+                }
+            })
+            .collect()
+    }
+}
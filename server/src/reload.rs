@@ -0,0 +1,220 @@
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::{debug, error, info, warn};
+
+use crate::config::{AuthConfig, ConfigSource, ServerConfig};
+
+/// Configuration file modification time is checked this often
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Settings changed between `old` and `new` that only apply after a restart
+pub fn restart_required(old: &ServerConfig, new: &ServerConfig) -> Vec<&'static str> {
+    let checks = [
+        (
+            "grpc_listen_addr",
+            old.grpc_listen_addr != new.grpc_listen_addr,
+        ),
+        ("tls", old.tls != new.tls),
+        ("ws_listen_addr", old.ws_listen_addr != new.ws_listen_addr),
+        (
+            "http_listen_addr",
+            old.http_listen_addr != new.http_listen_addr,
+        ),
+        // Fee schedules are set up by the merge, arbitrage and routing
+        ("binance.fees", old.binance.fees != new.binance.fees),
+        ("bitstamp.fees", old.bitstamp.fees != new.bitstamp.fees),
+        ("merge", old.merge != new.merge),
+        ("fx", old.fx != new.fx),
+        (
+            "validation.enabled",
+            old.validation.enabled != new.validation.enabled,
+        ),
+        ("arbitrage", old.arbitrage != new.arbitrage),
+        ("metrics", old.metrics != new.metrics),
+        ("bars", old.bars != new.bars),
+        (
+            "trades.window_secs",
+            old.trades.window_secs != new.trades.window_secs,
+        ),
+        ("log", old.log != new.log),
+        ("latency", old.latency != new.latency),
+        ("book_updates", old.book_updates != new.book_updates),
+        ("auth", live_auth(&old.auth, &new.auth) != new.auth),
+    ];
+    checks
+        .iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// Running auth keys with instruments of the same keys in `new`
+fn live_auth(running: &Option<AuthConfig>, new: &Option<AuthConfig>) -> Option<AuthConfig> {
+    let mut auth = running.clone();
+    if let (Some(auth), Some(new)) = (auth.as_mut(), new) {
+        for key in &mut auth.keys {
+            if let Some(k) = new.keys.iter().find(|k| k.key == key.key) {
+                key.instruments = k.instruments.clone();
+            }
+        }
+    }
+    auth
+}
+
+/// Running configuration with live settings taken from `new`: instrument,
+/// exchange connectors except fees, `trades.enabled`, validation
+/// thresholds, alerts, health staleness and instruments of auth keys
+pub fn apply(running: &ServerConfig, new: &ServerConfig) -> ServerConfig {
+    let mut cfg = running.clone();
+    cfg.instrument = new.instrument.clone();
+    cfg.binance = new.binance.clone();
+    cfg.binance.fees = running.binance.fees;
+    cfg.bitstamp = new.bitstamp.clone();
+    cfg.bitstamp.fees = running.bitstamp.fees;
+    cfg.trades.enabled = new.trades.enabled;
+    cfg.validation = new.validation.clone();
+    cfg.validation.enabled = running.validation.enabled;
+    cfg.alerts = new.alerts.clone();
+    cfg.health = new.health.clone();
+    cfg.auth = live_auth(&running.auth, &new.auth);
+    cfg
+}
+
+/// Reloads the configuration when its file is modified or on SIGHUP and
+/// publishes the running configuration with live changes applied.
+/// Environment and command line overrides are applied on every reload.
+pub struct ConfigWatcher {
+    source: ConfigSource,
+    tx: watch::Sender<ServerConfig>,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    pub fn new(source: ConfigSource, config: ServerConfig) -> Self {
+        Self {
+            modified: Self::modified(&source.path),
+            source,
+            tx: watch::channel(config).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<ServerConfig> {
+        self.tx.subscribe()
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    pub async fn run(mut self) {
+        let mut hangup = signal(SignalKind::hangup())
+            .map_err(|e| warn!(error = %e, "SIGHUP handler not installed"))
+            .ok();
+        let mut ticker = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            let hangup = async {
+                match hangup.as_mut() {
+                    Some(h) => h.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let modified = Self::modified(&self.source.path);
+                    if modified != self.modified {
+                        self.modified = modified;
+                        self.reload("file modified");
+                    }
+                }
+                _ = hangup => self.reload("SIGHUP"),
+            }
+        }
+    }
+
+    fn reload(&mut self, trigger: &str) {
+        let new = match self.source.load() {
+            Ok(new) => new,
+            Err(e) => {
+                error!(%trigger, error = ?e, "Configuration not reloaded");
+                return;
+            }
+        };
+        let problems = new.validate();
+        if !problems.is_empty() {
+            error!(%trigger, ?problems, "Invalid configuration not reloaded");
+            return;
+        }
+        let running = self.tx.borrow().clone();
+        let restart = restart_required(&running, &new);
+        if !restart.is_empty() {
+            warn!(
+                settings = ?restart,
+                "Changed settings need a restart, running values kept"
+            );
+        }
+        let applied = apply(&running, &new);
+        if applied == running {
+            debug!(%trigger, "No live configuration changes");
+            return;
+        }
+        info!(%trigger, "Configuration reloaded");
+        self.tx.send_replace(applied);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, restart_required};
+    use crate::config::ServerConfig;
+
+    #[test]
+    fn test_reload() {
+        let parse = |s: &str| -> ServerConfig { serde_yaml::from_str(s).unwrap() };
+        let running = parse(
+            r#"
+grpc_listen_addr: 127.0.0.1:12345
+binance: { symbol: BTCUSDT, depth: 10 }
+bitstamp: { symbol: BTCUSDT }
+auth: { keys: [{ name: partner, key: secret, instruments: [BTCUSDT] }] }
+"#,
+        );
+        let new = parse(
+            r#"
+grpc_listen_addr: 127.0.0.1:5000
+binance: { symbol: ETHUSDT, depth: 20, fees: { taker: 0.001 } }
+bitstamp: { symbol: ETHUSDT, enabled: false }
+validation: { max_deviation_bps: 100 }
+alerts: { rules: [{ name: wide, condition: { type: spread_above, value: 10 } }] }
+auth: { keys: [{ name: partner, key: secret, instruments: [ETHUSDT] }] }
+health: { max_update_age_secs: 30 }
+"#,
+        );
+
+        assert_eq!(
+            restart_required(&running, &new),
+            vec!["grpc_listen_addr", "binance.fees"]
+        );
+        let applied = apply(&running, &new);
+        assert_eq!(applied.grpc_listen_addr, "127.0.0.1:12345");
+        assert_eq!(applied.instrument(), "ETHUSDT");
+        assert_eq!(
+            (applied.binance.symbol.as_str(), applied.binance.depth),
+            ("ETHUSDT", 20)
+        );
+        assert_eq!(applied.bitstamp.symbol, "ETHUSDT");
+        assert_eq!(applied.binance.fees.taker, 0.0);
+        assert!(!applied.bitstamp.enabled);
+        assert_eq!(applied.validation.max_deviation_bps, 100.0);
+        assert_eq!(applied.alerts.rules.len(), 1);
+        assert_eq!(applied.health.max_update_age_secs, 30);
+        assert_eq!(
+            applied.auth.as_ref().unwrap().keys[0].instruments,
+            ["ETHUSDT"]
+        );
+        assert!(applied.validate().is_empty());
+        assert!(restart_required(&running, &applied).is_empty());
+    }
+}
@@ -1,18 +1,119 @@
-use tokio::sync::watch;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use strum::IntoEnumIterator;
+use tokio::sync::{broadcast, mpsc, watch};
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::Response;
+use tracing::debug;
 
+use crate::auth::{ClientPermissions, StreamGuard};
+use crate::bars::BarStore;
+use crate::bbo;
+use crate::book_diff;
+use crate::config::ServerConfig;
+use crate::fees::FeeSchedule;
+use crate::latency::{now_us, LatencyRecorder, Stage};
+use crate::quote::{self, Liquidity};
+use crate::registry::ExchangeRegistry;
 use crate::server::orderbook_aggregator_server::OrderbookAggregator;
+use crate::sor::{Simulation, VenueRules};
+use crate::subscription::{origin, SubscriptionPolicy};
+use crate::Exchange;
 
 tonic::include_proto!("orderbook");
 
+/// Encoded proto descriptors for gRPC server reflection
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("orderbook_descriptor");
+
+/// Resting `SimulateOrder` children are cancelled after
+const DEFAULT_SIMULATION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Spawns the loop of a client stream. The loop is stopped and the stream
+/// slot released as soon as the client disconnects, also while the loop
+/// waits for the next update.
+fn spawn_stream<T, F>(tx: mpsc::Sender<T>, stream_guard: Option<StreamGuard>, stream: F)
+where
+    T: Send + 'static,
+    F: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        // Released when client disconnects
+        let _stream_guard = stream_guard;
+        tokio::select! {
+            _ = stream => {}
+            // Client disconnected
+            _ = tx.closed() => {}
+        }
+    });
+}
+
+/// Data streams served to clients
+#[derive(Clone)]
+pub struct Feeds {
+    /// Merged book
+    pub summary: watch::Receiver<Summary>,
+    /// Full per exchange books
+    pub books: watch::Receiver<Vec<crate::OrderBook>>,
+    pub metrics: watch::Receiver<MarketMetrics>,
+    pub opportunities: broadcast::Sender<Opportunity>,
+    pub bars: BarStore,
+    pub trades: broadcast::Sender<Trade>,
+    /// Real-time top of book per exchange
+    pub bbo: watch::Receiver<Vec<VenueBbo>>,
+}
+
 pub struct OrderbookServer {
-    rx: watch::Receiver<Summary>,
+    feeds: Feeds,
+    latency: LatencyRecorder,
+    registry: ExchangeRegistry,
+    policy: SubscriptionPolicy,
+    fees: FeeSchedule,
+    /// Maximum time between `BookUpdates` snapshots
+    checkpoint_interval: Duration,
 }
 
 impl OrderbookServer {
-    pub fn new(rx: watch::Receiver<Summary>) -> Self {
-        Self { rx }
+    pub fn new(
+        feeds: Feeds,
+        latency: LatencyRecorder,
+        registry: ExchangeRegistry,
+        config: watch::Receiver<ServerConfig>,
+    ) -> Self {
+        let cfg = config.borrow().clone();
+        Self {
+            feeds,
+            latency,
+            registry,
+            policy: SubscriptionPolicy::new(config),
+            fees: FeeSchedule::new(&cfg),
+            checkpoint_interval: Duration::from_secs(cfg.book_updates.checkpoint_interval_secs),
+        }
+    }
+
+    /// Current merged book as seen by a client with given request and permissions
+    pub fn snapshot(
+        &self,
+        request: &BookRequest,
+        permissions: Option<&Arc<ClientPermissions>>,
+    ) -> Result<Summary, tonic::Status> {
+        let subscription = self.policy.subscribe(request, permissions)?;
+        let summary = subscription.view(&self.feeds.summary.borrow(), &self.feeds.books.borrow());
+        Ok(summary)
+    }
+
+    /// Requested bar interval if configured
+    fn bar_interval(&self, request: &BarsRequest) -> Result<u32, tonic::Status> {
+        let intervals = self.feeds.bars.intervals();
+        if intervals.contains(&request.interval_secs) {
+            Ok(request.interval_secs)
+        } else {
+            Err(tonic::Status::invalid_argument(format!(
+                "Bar interval {}s not configured, available {:?}",
+                request.interval_secs, intervals
+            )))
+        }
     }
 }
 
@@ -22,18 +123,38 @@ impl OrderbookAggregator for OrderbookServer {
 
     async fn book_summary(
         &self,
-        _request: tonic::Request<Empty>,
+        request: tonic::Request<BookRequest>,
     ) -> Result<tonic::Response<Self::BookSummaryStream>, tonic::Status> {
+        let permissions = request
+            .extensions()
+            .get::<Arc<ClientPermissions>>()
+            .cloned();
+        let subscription = self
+            .policy
+            .subscribe(request.get_ref(), permissions.as_ref())?;
+        let stream_guard = permissions
+            .as_ref()
+            .map(ClientPermissions::acquire_stream)
+            .transpose()?;
+
         let (tx, rx) = tokio::sync::mpsc::channel(100);
-        let mut watch_rx = self.rx.clone();
-        tokio::spawn(async move {
+        let mut watch_rx = self.feeds.summary.clone();
+        let books = self.feeds.books.clone();
+        let latency = self.latency.clone();
+        spawn_stream(tx.clone(), stream_guard, async move {
             loop {
                 if watch_rx.changed().await.is_ok() {
-                    let summary = watch_rx.borrow().clone();
+                    let (summary, origin) = {
+                        let merged = watch_rx.borrow();
+                        (subscription.view(&merged, &books.borrow()), origin(&merged))
+                    };
                     if let Err(_e) = tx.send(Ok(summary)).await {
                         // Client disconnected
                         break;
                     }
+                    if let Some((exchange, merged)) = origin {
+                        latency.record(exchange, Stage::Delivery, merged, now_us());
+                    }
                 } else {
                     // Listener has dropped app is shutting down
                     break;
@@ -43,4 +164,466 @@ impl OrderbookAggregator for OrderbookServer {
 
         Ok(Response::new(ReceiverStream::new(rx)))
     }
+
+    type BookUpdatesStream = ReceiverStream<Result<BookUpdate, tonic::Status>>;
+
+    async fn book_updates(
+        &self,
+        request: tonic::Request<BookRequest>,
+    ) -> Result<tonic::Response<Self::BookUpdatesStream>, tonic::Status> {
+        let permissions = request
+            .extensions()
+            .get::<Arc<ClientPermissions>>()
+            .cloned();
+        let subscription = self
+            .policy
+            .subscribe(request.get_ref(), permissions.as_ref())?;
+        let stream_guard = permissions
+            .as_ref()
+            .map(ClientPermissions::acquire_stream)
+            .transpose()?;
+
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        let mut watch_rx = self.feeds.summary.clone();
+        let books = self.feeds.books.clone();
+        let latency = self.latency.clone();
+        let checkpoint_interval = self.checkpoint_interval;
+        spawn_stream(tx.clone(), stream_guard, async move {
+            let mut sequence = 0;
+            let mut last: Option<Summary> = None;
+            let mut last_checkpoint = Instant::now();
+            loop {
+                if watch_rx.changed().await.is_err() {
+                    // Listener has dropped app is shutting down
+                    break;
+                }
+                let (summary, origin) = {
+                    let merged = watch_rx.borrow();
+                    (subscription.view(&merged, &books.borrow()), origin(&merged))
+                };
+
+                let update = match &last {
+                    Some(prev) if last_checkpoint.elapsed() < checkpoint_interval => {
+                        let diff = book_diff::diff(prev, &summary);
+                        if diff.bids.is_empty() && diff.asks.is_empty() {
+                            // Change not visible at subscribed depth
+                            continue;
+                        }
+                        book_update::Update::Diff(diff)
+                    }
+                    _ => {
+                        last_checkpoint = Instant::now();
+                        book_update::Update::Snapshot(summary.clone())
+                    }
+                };
+
+                sequence += 1;
+                let msg = BookUpdate {
+                    sequence,
+                    update: Some(update),
+                };
+                if let Err(_e) = tx.send(Ok(msg)).await {
+                    // Client disconnected
+                    break;
+                }
+                if let Some((exchange, merged)) = origin {
+                    latency.record(exchange, Stage::Delivery, merged, now_us());
+                }
+                last = Some(summary);
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn latency_stats(
+        &self,
+        _request: tonic::Request<Empty>,
+    ) -> Result<tonic::Response<LatencyReport>, tonic::Status> {
+        Ok(Response::new(self.latency.report()))
+    }
+
+    async fn list_exchanges(
+        &self,
+        _request: tonic::Request<Empty>,
+    ) -> Result<tonic::Response<ExchangeList>, tonic::Status> {
+        Ok(Response::new(ExchangeList {
+            exchanges: self.registry.exchanges(),
+        }))
+    }
+
+    async fn list_instruments(
+        &self,
+        request: tonic::Request<Empty>,
+    ) -> Result<tonic::Response<InstrumentList>, tonic::Status> {
+        let permissions = request.extensions().get::<Arc<ClientPermissions>>();
+        let instruments = self
+            .registry
+            .instruments()
+            .into_iter()
+            .filter(|i| {
+                permissions
+                    .map(|p| p.check_instrument(&i.instrument).is_ok())
+                    .unwrap_or(true)
+            })
+            .collect();
+        Ok(Response::new(InstrumentList { instruments }))
+    }
+
+    type OpportunitiesStream = ReceiverStream<Result<Opportunity, tonic::Status>>;
+
+    async fn opportunities(
+        &self,
+        request: tonic::Request<Empty>,
+    ) -> Result<tonic::Response<Self::OpportunitiesStream>, tonic::Status> {
+        let permissions = request
+            .extensions()
+            .get::<Arc<ClientPermissions>>()
+            .cloned();
+        // Opportunities are of the default instrument
+        self.policy
+            .subscribe(&BookRequest::default(), permissions.as_ref())?;
+        let stream_guard = permissions
+            .as_ref()
+            .map(ClientPermissions::acquire_stream)
+            .transpose()?;
+
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        let mut opportunities = self.feeds.opportunities.subscribe();
+        spawn_stream(tx.clone(), stream_guard, async move {
+            loop {
+                match opportunities.recv().await {
+                    Ok(o) => {
+                        if let Err(_e) = tx.send(Ok(o)).await {
+                            // Client disconnected
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        debug!(skipped, "Opportunities stream lagging");
+                    }
+                    // Detector dropped - app is shutting down
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn quote(
+        &self,
+        request: tonic::Request<QuoteRequest>,
+    ) -> Result<tonic::Response<QuoteResponse>, tonic::Status> {
+        let permissions = request.extensions().get::<Arc<ClientPermissions>>();
+        let req = request.get_ref();
+        let subscription = self.policy.subscribe(
+            &BookRequest {
+                exchanges: req.exchanges.clone(),
+                ..Default::default()
+            },
+            permissions,
+        )?;
+        let fees = if req.include_fees {
+            Some(&self.fees)
+        } else {
+            None
+        };
+
+        let (bids, asks) = if req.full_depth {
+            if permissions
+                .map(|p| p.limit_depth(usize::MAX) < usize::MAX)
+                .unwrap_or(false)
+            {
+                return Err(tonic::Status::permission_denied(
+                    "Full depth not allowed with depth limited key",
+                ));
+            }
+            let books = self.feeds.books.borrow();
+            let side = |bids: bool| -> Vec<Liquidity> {
+                books
+                    .iter()
+                    .flat_map(|b| Liquidity::from_orders(if bids { &b.bids } else { &b.asks }))
+                    .filter(|l| subscription.includes(l.exchange.id() as i32))
+                    .collect()
+            };
+            (side(true), side(false))
+        } else {
+            let summary =
+                subscription.view(&self.feeds.summary.borrow(), &self.feeds.books.borrow());
+            (
+                Liquidity::from_levels(&summary.bids),
+                Liquidity::from_levels(&summary.asks),
+            )
+        };
+
+        quote::quote(req, bids, asks, fees).map(Response::new)
+    }
+
+    type SimulateOrderStream = ReceiverStream<Result<SimulationReport, tonic::Status>>;
+
+    async fn simulate_order(
+        &self,
+        request: tonic::Request<SimulationRequest>,
+    ) -> Result<tonic::Response<Self::SimulateOrderStream>, tonic::Status> {
+        let permissions = request
+            .extensions()
+            .get::<Arc<ClientPermissions>>()
+            .cloned();
+        let req = request.into_inner();
+        let subscription = self.policy.subscribe(
+            &BookRequest {
+                exchanges: req.exchanges.clone(),
+                ..Default::default()
+            },
+            permissions.as_ref(),
+        )?;
+        let stream_guard = permissions
+            .as_ref()
+            .map(ClientPermissions::acquire_stream)
+            .transpose()?;
+
+        let venues = Exchange::iter()
+            .map(|e| {
+                VenueRules::new(
+                    &self.registry.rules(e),
+                    self.fees.get(e),
+                    subscription.includes(e.id() as i32),
+                )
+            })
+            .collect();
+        let mut books = self.feeds.books.clone();
+        let mut sim = Simulation::new(&req, venues, &books.borrow_and_update())?;
+        let timeout = match req.timeout_secs {
+            0 => DEFAULT_SIMULATION_TIMEOUT,
+            t => Duration::from_secs(t as u64),
+        };
+
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        spawn_stream(tx.clone(), stream_guard, async move {
+            let deadline = tokio::time::sleep(timeout);
+            tokio::pin!(deadline);
+            let status = loop {
+                if !sim.working() {
+                    break simulation_report::Status::Done;
+                }
+                if tx
+                    .send(Ok(sim.report(simulation_report::Status::Working)))
+                    .await
+                    .is_err()
+                {
+                    // Client disconnected
+                    return;
+                }
+                tokio::select! {
+                    changed = books.changed() => {
+                        if changed.is_err() {
+                            // Listener has dropped app is shutting down
+                            return;
+                        }
+                        sim.on_books(&books.borrow_and_update());
+                    }
+                    _ = &mut deadline => break simulation_report::Status::Expired,
+                }
+            };
+            let _ = tx.send(Ok(sim.report(status))).await;
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type MetricsStream = ReceiverStream<Result<MarketMetrics, tonic::Status>>;
+
+    async fn metrics(
+        &self,
+        request: tonic::Request<MetricsRequest>,
+    ) -> Result<tonic::Response<Self::MetricsStream>, tonic::Status> {
+        let permissions = request
+            .extensions()
+            .get::<Arc<ClientPermissions>>()
+            .cloned();
+        // Metrics are of the default instrument
+        self.policy
+            .subscribe(&BookRequest::default(), permissions.as_ref())?;
+        let stream_guard = permissions
+            .as_ref()
+            .map(ClientPermissions::acquire_stream)
+            .transpose()?;
+        let interval = Duration::from_millis(request.get_ref().interval_ms as u64);
+
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        let mut metrics = self.feeds.metrics.clone();
+        spawn_stream(tx.clone(), stream_guard, async move {
+            while metrics.changed().await.is_ok() {
+                let m = metrics.borrow_and_update().clone();
+                if let Err(_e) = tx.send(Ok(m)).await {
+                    // Client disconnected
+                    break;
+                }
+                if !interval.is_zero() {
+                    tokio::time::sleep(interval).await;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type BarsStream = ReceiverStream<Result<Bar, tonic::Status>>;
+
+    async fn bars(
+        &self,
+        request: tonic::Request<BarsRequest>,
+    ) -> Result<tonic::Response<Self::BarsStream>, tonic::Status> {
+        let permissions = request
+            .extensions()
+            .get::<Arc<ClientPermissions>>()
+            .cloned();
+        // Bars are of the default instrument
+        self.policy
+            .subscribe(&BookRequest::default(), permissions.as_ref())?;
+        let interval_secs = self.bar_interval(request.get_ref())?;
+        let stream_guard = permissions
+            .as_ref()
+            .map(ClientPermissions::acquire_stream)
+            .transpose()?;
+
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        let mut bars = self.feeds.bars.subscribe();
+        spawn_stream(tx.clone(), stream_guard, async move {
+            loop {
+                match bars.recv().await {
+                    Ok(bar) if bar.interval_secs == interval_secs => {
+                        if let Err(_e) = tx.send(Ok(bar)).await {
+                            // Client disconnected
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        debug!(skipped, "Bars stream lagging");
+                    }
+                    // Bar builder dropped - app is shutting down
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn bar_history(
+        &self,
+        request: tonic::Request<BarsRequest>,
+    ) -> Result<tonic::Response<BarList>, tonic::Status> {
+        let permissions = request.extensions().get::<Arc<ClientPermissions>>();
+        self.policy
+            .subscribe(&BookRequest::default(), permissions)?;
+        let req = request.get_ref();
+        let interval_secs = self.bar_interval(req)?;
+        let bars = self
+            .feeds
+            .bars
+            .history(interval_secs, req.limit as usize)
+            .unwrap_or_default();
+        Ok(Response::new(BarList { bars }))
+    }
+
+    type TradesStream = ReceiverStream<Result<Trade, tonic::Status>>;
+
+    async fn trades(
+        &self,
+        request: tonic::Request<TradesRequest>,
+    ) -> Result<tonic::Response<Self::TradesStream>, tonic::Status> {
+        let permissions = request
+            .extensions()
+            .get::<Arc<ClientPermissions>>()
+            .cloned();
+        let subscription = self.policy.subscribe(
+            &BookRequest {
+                exchanges: request.get_ref().exchanges.clone(),
+                ..Default::default()
+            },
+            permissions.as_ref(),
+        )?;
+        let stream_guard = permissions
+            .as_ref()
+            .map(ClientPermissions::acquire_stream)
+            .transpose()?;
+
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        let mut trades = self.feeds.trades.subscribe();
+        spawn_stream(tx.clone(), stream_guard, async move {
+            loop {
+                match trades.recv().await {
+                    Ok(t) if subscription.includes(t.exchange_id) => {
+                        if let Err(_e) = tx.send(Ok(t)).await {
+                            // Client disconnected
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        debug!(skipped, "Trades stream lagging");
+                    }
+                    // Trade tape dropped - app is shutting down
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type BestBidOfferStream = ReceiverStream<Result<Bbo, tonic::Status>>;
+
+    async fn best_bid_offer(
+        &self,
+        request: tonic::Request<BboRequest>,
+    ) -> Result<tonic::Response<Self::BestBidOfferStream>, tonic::Status> {
+        let permissions = request
+            .extensions()
+            .get::<Arc<ClientPermissions>>()
+            .cloned();
+        let subscription = self.policy.subscribe(
+            &BookRequest {
+                exchanges: request.get_ref().exchanges.clone(),
+                ..Default::default()
+            },
+            permissions.as_ref(),
+        )?;
+        let stream_guard = permissions
+            .as_ref()
+            .map(ClientPermissions::acquire_stream)
+            .transpose()?;
+
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        let mut venues = self.feeds.bbo.clone();
+        spawn_stream(tx.clone(), stream_guard, async move {
+            let mut last = None;
+            while venues.changed().await.is_ok() {
+                let selected = venues
+                    .borrow_and_update()
+                    .iter()
+                    .filter(|v| subscription.includes(v.exchange_id))
+                    .cloned()
+                    .collect();
+                let merged = bbo::merge(selected);
+                // Changes of venues not subscribed to leave the result unchanged
+                if merged == last {
+                    continue;
+                }
+                if let Some(bbo) = &merged {
+                    if let Err(_e) = tx.send(Ok(bbo.clone())).await {
+                        // Client disconnected
+                        break;
+                    }
+                }
+                last = merged;
+            }
+            // Merger dropped - app is shutting down
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
 }
@@ -0,0 +1,538 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+
+use strum::{EnumCount, IntoEnumIterator};
+
+use crate::config::FeeConfig;
+use crate::registry::SymbolRules;
+use crate::server::{
+    simulation_report::Status, ChildOrder, OrderType, Side, SimulationReport, SimulationRequest,
+};
+use crate::{Exchange, Order, OrderBook};
+
+/// Trading constraints of one exchange
+#[derive(Debug, Clone, Default)]
+pub struct VenueRules {
+    /// Exchange may be routed to
+    pub enabled: bool,
+    pub fees: FeeConfig,
+    pub lot_size: f64,
+    pub min_quantity: f64,
+    pub min_notional: f64,
+}
+
+impl VenueRules {
+    pub fn new(rules: &SymbolRules, fees: FeeConfig, enabled: bool) -> Self {
+        Self {
+            enabled,
+            fees,
+            lot_size: rules.lot_size,
+            min_quantity: rules.min_quantity,
+            min_notional: rules.min_notional,
+        }
+    }
+
+    /// Quantity rounded down to lot size, zero if below exchange minimums
+    fn tradable(&self, quantity: f64, price: f64) -> f64 {
+        let q = if self.lot_size > 0.0 {
+            (quantity / self.lot_size + 1e-9).floor() * self.lot_size
+        } else {
+            quantity
+        };
+        if q <= 0.0 || q < self.min_quantity || q * price < self.min_notional {
+            0.0
+        } else {
+            q
+        }
+    }
+}
+
+struct Child {
+    exchange: Exchange,
+    order: ChildOrder,
+    /// Amount at the passive child price in the last seen book
+    level_amount: f64,
+}
+
+/// Parent order split into child orders across exchanges, with fills simulated
+/// against subsequent book updates. Books are indexed by `Exchange`, as published
+/// by `ExchangeListener`, so live and replayed data can be used alike.
+pub struct Simulation {
+    side: Side,
+    order_type: OrderType,
+    quantity: f64,
+    limit_price: f64,
+    /// Indexed by `Exchange`
+    venues: Vec<VenueRules>,
+    arrival_mid: f64,
+    mid: f64,
+    children: Vec<Child>,
+    /// Last processed book per exchange, books are compared by content
+    /// as replayed books may repeat timestamps
+    last_books: Vec<OrderBook>,
+}
+
+impl Simulation {
+    /// Validates the order and routes it against current books
+    pub fn new(
+        req: &SimulationRequest,
+        venues: Vec<VenueRules>,
+        books: &[OrderBook],
+    ) -> Result<Self, tonic::Status> {
+        let side = Side::try_from(req.side)
+            .map_err(|_| tonic::Status::invalid_argument(format!("Unknown side {}", req.side)))?;
+        let order_type = OrderType::try_from(req.r#type).map_err(|_| {
+            tonic::Status::invalid_argument(format!("Unknown order type {}", req.r#type))
+        })?;
+        let valid = |v: f64| v.is_finite() && v > 0.0;
+        if !valid(req.quantity) {
+            return Err(tonic::Status::invalid_argument(
+                "Positive quantity required",
+            ));
+        }
+        if order_type != OrderType::Market && !valid(req.limit_price) {
+            return Err(tonic::Status::invalid_argument(
+                "Positive limit price required",
+            ));
+        }
+
+        let mut sim = Self {
+            side,
+            order_type,
+            quantity: req.quantity,
+            limit_price: req.limit_price,
+            venues,
+            arrival_mid: 0.0,
+            mid: 0.0,
+            children: vec![],
+            last_books: books.to_vec(),
+        };
+        sim.arrival_mid = sim
+            .compute_mid(books)
+            .ok_or_else(|| tonic::Status::failed_precondition("Book not available"))?;
+        sim.mid = sim.arrival_mid;
+        sim.route(books);
+        Ok(sim)
+    }
+
+    fn buy(&self) -> bool {
+        self.side == Side::Buy
+    }
+
+    /// Routable exchanges with their books
+    fn venue_books<'a>(
+        &'a self,
+        books: &'a [OrderBook],
+    ) -> impl Iterator<Item = (Exchange, &'a OrderBook)> + 'a {
+        Exchange::iter()
+            .zip(books)
+            .filter(move |(e, _)| self.venues[*e as usize].enabled)
+    }
+
+    /// Liquidity taken by the parent order
+    fn taken_side<'b>(&self, book: &'b OrderBook) -> &'b [Order] {
+        if self.buy() {
+            &book.asks
+        } else {
+            &book.bids
+        }
+    }
+
+    fn within_limit(&self, price: f64) -> bool {
+        match self.order_type {
+            OrderType::Market => true,
+            _ if self.buy() => price <= self.limit_price,
+            _ => price >= self.limit_price,
+        }
+    }
+
+    /// Mid of best bid and ask across routable exchanges
+    fn compute_mid(&self, books: &[OrderBook]) -> Option<f64> {
+        let best = |f: fn(&OrderBook) -> Option<f64>, cmp: fn(f64, f64) -> f64| {
+            self.venue_books(books)
+                .filter_map(|(_, b)| f(b))
+                .fold(None, |acc: Option<f64>, p| {
+                    Some(acc.map_or(p, |a| cmp(a, p)))
+                })
+        };
+        let bid = best(|b| b.bids.first().map(|o| o.price), f64::max)?;
+        let ask = best(|b| b.asks.first().map(|o| o.price), f64::min)?;
+        Some((bid + ask) / 2.0)
+    }
+
+    /// Splits the parent into taker children by taker fee adjusted price, exchanges
+    /// whose share is below their minimum size are excluded. LIMIT remainder rests
+    /// on the exchange with the lowest maker fee.
+    fn route(&mut self, books: &[OrderBook]) {
+        let buy = self.buy();
+        let mut excluded = [false; Exchange::COUNT];
+        let allocation = loop {
+            let mut levels: Vec<(f64, Exchange, &Order)> = self
+                .venue_books(books)
+                .filter(|(e, _)| !excluded[*e as usize])
+                .flat_map(|(e, b)| self.taken_side(b).iter().map(move |o| (e, o)))
+                .filter(|(_, o)| self.within_limit(o.price))
+                .map(|(e, o)| {
+                    let fee = self.venues[e as usize].fees.taker;
+                    let effective = if buy {
+                        o.price * (1.0 + fee)
+                    } else {
+                        o.price * (1.0 - fee)
+                    };
+                    (effective, e, o)
+                })
+                .collect();
+            levels.sort_by(|a, b| {
+                let ord = a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal);
+                if buy {
+                    ord
+                } else {
+                    ord.reverse()
+                }
+            });
+
+            let mut allocation = vec![0.0; Exchange::COUNT];
+            let mut remaining = self.quantity;
+            for (_, e, o) in levels {
+                if remaining <= 0.0 {
+                    break;
+                }
+                let q = remaining.min(o.quantity);
+                allocation[e as usize] += q;
+                remaining -= q;
+            }
+
+            let below_minimum = Exchange::iter().zip(books).find(|(e, b)| {
+                let idx = *e as usize;
+                let price = self.taken_side(b).first().map(|o| o.price).unwrap_or(0.0);
+                allocation[idx] > 0.0 && self.venues[idx].tradable(allocation[idx], price) == 0.0
+            });
+            match below_minimum {
+                Some((e, _)) => excluded[e as usize] = true,
+                None => break allocation,
+            }
+        };
+
+        let mut taken = 0.0;
+        for (e, book) in Exchange::iter().zip(books) {
+            let idx = e as usize;
+            if allocation[idx] <= 0.0 {
+                continue;
+            }
+            let side = self.taken_side(book);
+            let price = side.first().map(|o| o.price).unwrap_or(0.0);
+            let quantity = self.venues[idx].tradable(allocation[idx], price);
+            let (mut left, mut notional, mut worst) = (quantity, 0.0, price);
+            for o in side.iter().take_while(|o| self.within_limit(o.price)) {
+                if left <= 0.0 {
+                    break;
+                }
+                let q = left.min(o.quantity);
+                notional += q * o.price;
+                worst = o.price;
+                left -= q;
+            }
+            let filled = quantity - left.max(0.0);
+            taken += filled;
+            self.children.push(Child {
+                exchange: e,
+                order: ChildOrder {
+                    exchange_id: e.id() as i32,
+                    passive: false,
+                    price: worst,
+                    quantity: filled,
+                    filled,
+                    notional,
+                    fee: notional * self.venues[idx].fees.taker,
+                    queue_ahead: 0.0,
+                },
+                level_amount: 0.0,
+            });
+        }
+
+        let remaining = self.quantity - taken;
+        if self.order_type != OrderType::Limit || remaining <= 0.0 {
+            return;
+        }
+        let resting = self
+            .venue_books(books)
+            .map(|(e, b)| {
+                let q = self.venues[e as usize].tradable(remaining, self.limit_price);
+                (e, b, q)
+            })
+            .filter(|(_, _, q)| *q > 0.0)
+            .min_by(|a, b| {
+                let fee = |e: Exchange| self.venues[e as usize].fees.maker;
+                fee(a.0).partial_cmp(&fee(b.0)).unwrap_or(Ordering::Equal)
+            });
+        if let Some((e, book, quantity)) = resting {
+            let same = if buy { &book.bids } else { &book.asks };
+            let level = same
+                .iter()
+                .find(|o| o.price == self.limit_price)
+                .map(|o| o.quantity)
+                .unwrap_or(0.0);
+            self.children.push(Child {
+                exchange: e,
+                order: ChildOrder {
+                    exchange_id: e.id() as i32,
+                    passive: true,
+                    price: self.limit_price,
+                    quantity,
+                    queue_ahead: level,
+                    ..Default::default()
+                },
+                level_amount: level,
+            });
+        }
+    }
+
+    /// Updates mid and fills of passive children from changed books
+    pub fn on_books(&mut self, books: &[OrderBook]) {
+        if let Some(mid) = self.compute_mid(books) {
+            self.mid = mid;
+        }
+        let buy = self.buy();
+        for (e, book) in Exchange::iter().zip(books) {
+            let idx = e as usize;
+            if same_levels(&self.last_books[idx], book) {
+                continue;
+            }
+            self.last_books[idx] = book.clone();
+            let maker = self.venues[idx].fees.maker;
+            for child in self
+                .children
+                .iter_mut()
+                .filter(|c| c.exchange == e && c.order.passive && c.order.filled < c.order.quantity)
+            {
+                let fill = passive_fill(child, book, buy);
+                let q = fill.min(child.order.quantity - child.order.filled);
+                if q > 0.0 {
+                    let notional = q * child.order.price;
+                    child.order.filled += q;
+                    child.order.notional += notional;
+                    child.order.fee += notional * maker;
+                }
+            }
+        }
+    }
+
+    /// Passive children are still resting
+    pub fn working(&self) -> bool {
+        self.children
+            .iter()
+            .any(|c| c.order.passive && c.order.filled < c.order.quantity)
+    }
+
+    pub fn report(&self, status: Status) -> SimulationReport {
+        let filled: f64 = self.children.iter().map(|c| c.order.filled).sum();
+        let notional: f64 = self.children.iter().map(|c| c.order.notional).sum();
+        let fees: f64 = self.children.iter().map(|c| c.order.fee).sum();
+        let (sign, cost) = if self.buy() {
+            (1.0, notional + fees)
+        } else {
+            (-1.0, notional - fees)
+        };
+
+        let execution_cost = sign * (cost - filled * self.arrival_mid);
+        let opportunity_cost = sign * (self.quantity - filled) * (self.mid - self.arrival_mid);
+        let (average_price, shortfall_bps) = if filled > 0.0 {
+            (
+                cost / filled,
+                execution_cost / (filled * self.arrival_mid) * 10_000.0,
+            )
+        } else {
+            (0.0, 0.0)
+        };
+        SimulationReport {
+            status: status as i32,
+            children: self.children.iter().map(|c| c.order.clone()).collect(),
+            filled,
+            average_price,
+            fees,
+            arrival_mid: self.arrival_mid,
+            mid: self.mid,
+            shortfall_bps,
+            opportunity_cost,
+            implementation_shortfall: execution_cost + opportunity_cost,
+        }
+    }
+}
+
+/// Quantity a resting child gets from a book update. Orders crossing the child
+/// price fill it, otherwise level depletion is assumed to be trades and
+/// consumes the queue ahead of the child first.
+fn passive_fill(child: &mut Child, book: &OrderBook, buy: bool) -> f64 {
+    let price = child.order.price;
+    let (same, opposite) = if buy {
+        (&book.bids, &book.asks)
+    } else {
+        (&book.asks, &book.bids)
+    };
+
+    let crossing: f64 = opposite
+        .iter()
+        .take_while(|o| {
+            if buy {
+                o.price <= price
+            } else {
+                o.price >= price
+            }
+        })
+        .map(|o| o.quantity)
+        .sum();
+    if crossing > 0.0 {
+        child.order.queue_ahead = 0.0;
+        child.level_amount = 0.0;
+        return crossing;
+    }
+
+    // Child price outside of visible depth, level state unknown
+    let visible = same
+        .last()
+        .map(|o| {
+            if buy {
+                o.price <= price
+            } else {
+                o.price >= price
+            }
+        })
+        .unwrap_or(false);
+    if !visible {
+        return 0.0;
+    }
+    let level = same
+        .iter()
+        .find(|o| o.price == price)
+        .map(|o| o.quantity)
+        .unwrap_or(0.0);
+    let depleted = (child.level_amount - level).max(0.0);
+    let ahead = depleted.min(child.order.queue_ahead);
+    child.order.queue_ahead -= ahead;
+    child.level_amount = level;
+    depleted - ahead
+}
+
+/// Books with equal prices and amounts on both sides
+fn same_levels(a: &OrderBook, b: &OrderBook) -> bool {
+    let same = |a: &[Order], b: &[Order]| {
+        a.len() == b.len()
+            && a.iter()
+                .zip(b)
+                .all(|(a, b)| a.price == b.price && a.quantity == b.quantity)
+    };
+    same(&a.bids, &b.bids) && same(&a.asks, &b.asks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Simulation, VenueRules};
+    use crate::config::FeeConfig;
+    use crate::server::{simulation_report::Status, OrderType, Side, SimulationRequest};
+    use crate::{Exchange, Order, OrderBook, Timestamps};
+
+    fn book(
+        exchange: Exchange,
+        received: u64,
+        bids: &[(f64, f64)],
+        asks: &[(f64, f64)],
+    ) -> OrderBook {
+        let orders = |levels: &[(f64, f64)]| {
+            levels
+                .iter()
+                .map(|&(price, quantity)| Order {
+                    price,
+                    quantity,
+                    id: 0,
+                    exchange,
+                })
+                .collect()
+        };
+        OrderBook {
+            exchange,
+            bids: orders(bids),
+            asks: orders(asks),
+            timestamps: Timestamps {
+                received,
+                ..Default::default()
+            },
+            conversion: None,
+        }
+    }
+
+    fn venue(maker: f64, min_quantity: f64) -> VenueRules {
+        VenueRules {
+            enabled: true,
+            fees: FeeConfig { maker, taker: 0.0 },
+            min_quantity,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_limit_order() {
+        let books = vec![
+            book(
+                Exchange::Binance,
+                1,
+                &[(100.2, 1.0), (99.0, 1.0)],
+                &[(100.0, 1.0), (101.0, 5.0)],
+            ),
+            book(
+                Exchange::Bitstamp,
+                1,
+                &[(99.5, 1.0)],
+                &[(100.5, 0.2), (102.0, 5.0)],
+            ),
+        ];
+        let req = SimulationRequest {
+            side: Side::Buy as i32,
+            r#type: OrderType::Limit as i32,
+            quantity: 3.0,
+            limit_price: 100.2,
+            ..Default::default()
+        };
+        // Remainder rests on Binance, lowest maker fee
+        let venues = vec![venue(0.0, 0.0), venue(0.001, 0.5)];
+        let mut sim = Simulation::new(&req, venues, &books).unwrap();
+        let report = sim.report(Status::Working);
+        assert_eq!(report.filled, 1.0);
+        assert_eq!(report.children.len(), 2);
+        assert!(report.children[1].passive);
+        assert_eq!(report.children[1].queue_ahead, 1.0);
+        assert!(sim.working());
+
+        // Half of the queue ahead traded
+        let mut books = books;
+        // Replayed books repeat timestamps
+        books[0] = book(
+            Exchange::Binance,
+            1,
+            &[(100.2, 0.5), (99.0, 1.0)],
+            &[(101.0, 5.0)],
+        );
+        sim.on_books(&books);
+        sim.on_books(&books);
+        assert_eq!(sim.report(Status::Working).children[1].queue_ahead, 0.5);
+
+        // Rest of the queue ahead traded
+        books[0] = book(
+            Exchange::Binance,
+            1,
+            &[(100.2, 0.0), (99.0, 1.0)],
+            &[(101.0, 5.0)],
+        );
+        books[0].bids.remove(0);
+        sim.on_books(&books);
+        assert_eq!(sim.report(Status::Working).filled, 1.0);
+
+        // Ask crosses child price
+        books[0] = book(Exchange::Binance, 1, &[(99.0, 1.0)], &[(100.1, 5.0)]);
+        sim.on_books(&books);
+        assert!(!sim.working());
+        let report = sim.report(Status::Done);
+        assert_eq!(report.filled, 3.0);
+        assert!((report.average_price - (100.0 + 2.0 * 100.2) / 3.0).abs() < 1e-9);
+        assert_eq!(report.opportunity_cost, 0.0);
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+use crate::aggregation::LevelAggregation;
+use crate::auth::ClientPermissions;
+use crate::config::ServerConfig;
+use crate::exchange_listener::{ExchangeListener, MAX_DEPTH};
+use crate::fees::FeeSchedule;
+use crate::latency::now_us;
+use crate::server::{BookRequest, ExchangeId, Summary};
+use crate::{Exchange, OrderBook};
+
+/// Subscription rules shared by every transport serving the merged book,
+/// taken from the live configuration when a client subscribes
+#[derive(Debug, Clone)]
+pub struct SubscriptionPolicy {
+    config: watch::Receiver<ServerConfig>,
+}
+
+impl SubscriptionPolicy {
+    pub fn new(config: watch::Receiver<ServerConfig>) -> Self {
+        Self { config }
+    }
+
+    /// Validates request against server state and client permissions
+    pub fn subscribe(
+        &self,
+        req: &BookRequest,
+        permissions: Option<&Arc<ClientPermissions>>,
+    ) -> Result<Subscription, tonic::Status> {
+        let cfg = self.config.borrow();
+        let tracked = cfg.instrument();
+        let instrument = if req.instrument.is_empty() {
+            tracked
+        } else {
+            &req.instrument
+        };
+        if instrument != tracked {
+            return Err(tonic::Status::not_found(format!(
+                "Unknown instrument {}",
+                instrument
+            )));
+        }
+
+        for id in &req.exchanges {
+            if ExchangeId::try_from(*id)
+                .ok()
+                .and_then(Exchange::from_id)
+                .is_none()
+            {
+                return Err(tonic::Status::invalid_argument(format!(
+                    "Unknown exchange id {}",
+                    id
+                )));
+            }
+        }
+
+        let aggregation = match &req.aggregation {
+            Some(a) => LevelAggregation::from_request(a)?,
+            None => None,
+        };
+
+        let mut depth = match req.depth as usize {
+            0 => MAX_DEPTH,
+            d => std::cmp::min(d, MAX_DEPTH),
+        };
+        if let Some(p) = permissions {
+            p.check_instrument(instrument)?;
+            depth = p.limit_depth(depth);
+        }
+        Ok(Subscription {
+            depth,
+            exchanges: req.exchanges.iter().copied().collect(),
+            aggregation,
+            fees: cfg.merge.fee_adjusted.then(|| FeeSchedule::new(&cfg)),
+            summary_latency: cfg.latency.summary_fields,
+            exchange_names: !req.omit_exchange_names,
+        })
+    }
+}
+
+/// Client specific view of the merged summary
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    depth: usize,
+    /// Exchange ids to include, all if empty
+    exchanges: HashSet<i32>,
+    aggregation: Option<LevelAggregation>,
+    /// Fees applied when aggregated levels are merged from full books
+    fees: Option<FeeSchedule>,
+    summary_latency: bool,
+    /// Keep `Level.exchange` names for clients not using `exchange_id`
+    exchange_names: bool,
+}
+
+impl Subscription {
+    /// Whether levels of the exchange are visible to the client
+    pub fn includes(&self, exchange_id: i32) -> bool {
+        self.exchanges.is_empty() || self.exchanges.contains(&exchange_id)
+    }
+
+    /// Client view of the merged summary. Aggregated levels are merged
+    /// from the full per exchange `books`, so buckets are not cut short
+    /// by the merged depth.
+    pub fn view(&self, merged: &Summary, books: &[OrderBook]) -> Summary {
+        let mut summary = merged.clone();
+        if self.aggregation.is_some() {
+            let fees = self.fees.as_ref();
+            summary.bids = ExchangeListener::merge_side(books, fees, true, usize::MAX);
+            summary.asks = ExchangeListener::merge_side(books, fees, false, usize::MAX);
+        }
+        if !self.exchanges.is_empty() {
+            summary
+                .bids
+                .retain(|l| self.exchanges.contains(&l.exchange_id));
+            summary
+                .asks
+                .retain(|l| self.exchanges.contains(&l.exchange_id));
+            summary
+                .trades
+                .retain(|t| self.exchanges.contains(&t.exchange_id));
+            summary.spread = match (summary.asks.first(), summary.bids.first()) {
+                (Some(ask), Some(bid)) => ask.price - bid.price,
+                _ => 0.0,
+            };
+        }
+        if let Some(aggregation) = self.aggregation {
+            aggregation.apply(&mut summary);
+        }
+        summary.bids.truncate(self.depth);
+        summary.asks.truncate(self.depth);
+        if !self.exchange_names {
+            for level in summary.bids.iter_mut().chain(summary.asks.iter_mut()) {
+                level.exchange.clear();
+            }
+        }
+        if self.summary_latency {
+            if let Some(t) = summary.latency.as_mut() {
+                t.sent_us = now_us();
+            }
+        } else {
+            summary.latency = None;
+        }
+        summary
+    }
+}
+
+/// Exchange and publish time of the update which produced the summary
+pub fn origin(summary: &Summary) -> Option<(Exchange, u64)> {
+    summary.latency.as_ref().and_then(|t| {
+        ExchangeId::try_from(t.exchange_id)
+            .ok()
+            .and_then(Exchange::from_id)
+            .map(|e| (e, t.merged_us))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::watch;
+
+    use super::SubscriptionPolicy;
+    use crate::config::ServerConfig;
+    use crate::server::{aggregation::Mode, Aggregation, BookRequest, Summary};
+    use crate::{Exchange, Order, OrderBook};
+
+    #[test]
+    fn test_tick_view_of_full_books() {
+        let cfg: ServerConfig = serde_yaml::from_str(
+            r#"
+grpc_listen_addr: 127.0.0.1:0
+binance: { symbol: BTCUSDT }
+bitstamp: { symbol: BTCUSDT }
+"#,
+        )
+        .unwrap();
+        let order = |exchange, price| Order {
+            price,
+            quantity: 1.0,
+            id: 0,
+            exchange,
+        };
+        // 12 levels per side within $0.12, more than the merged depth
+        let binance = OrderBook {
+            exchange: Exchange::Binance,
+            bids: (0..12)
+                .map(|i| order(Exchange::Binance, 100.5 - i as f64 * 0.01))
+                .collect(),
+            asks: (0..12)
+                .map(|i| order(Exchange::Binance, 100.6 + i as f64 * 0.01))
+                .collect(),
+            ..Default::default()
+        };
+        let bitstamp = OrderBook {
+            exchange: Exchange::Bitstamp,
+            bids: vec![order(Exchange::Bitstamp, 98.5)],
+            asks: vec![order(Exchange::Bitstamp, 102.5)],
+            ..Default::default()
+        };
+        let books = vec![binance, bitstamp];
+        let merged = Summary {
+            spread: 0.1,
+            ..Default::default()
+        };
+        let request = |depth| BookRequest {
+            depth,
+            aggregation: Some(Aggregation {
+                mode: Mode::Tick as i32,
+                tick: 1.0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let policy = SubscriptionPolicy::new(watch::channel(cfg).1);
+
+        let view = policy
+            .subscribe(&request(0), None)
+            .unwrap()
+            .view(&merged, &books);
+        assert_eq!(view.bids.len(), 2);
+        assert_eq!((view.bids[0].price, view.bids[0].amount), (100.0, 12.0));
+        assert_eq!((view.bids[1].price, view.bids[1].amount), (98.0, 1.0));
+        assert_eq!((view.asks[0].price, view.asks[0].amount), (101.0, 12.0));
+        assert_eq!(view.spread, 0.1);
+
+        // Depth applies to buckets
+        let view = policy
+            .subscribe(&request(1), None)
+            .unwrap()
+            .view(&merged, &books);
+        assert_eq!(view.bids.len(), 1);
+        assert_eq!(view.bids[0].amount, 12.0);
+        assert_eq!(view.asks.len(), 1);
+    }
+}
@@ -0,0 +1,14 @@
+//! Fixtures shared by unit tests
+
+use crate::server::{ExchangeId, Level};
+
+/// Merged book level of one exchange without fee adjustment
+pub fn level(exchange: ExchangeId, price: f64, amount: f64) -> Level {
+    Level {
+        exchange_id: exchange as i32,
+        price,
+        raw_price: price,
+        amount,
+        ..Default::default()
+    }
+}
@@ -0,0 +1,18 @@
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+use crate::{config::TlsConfig, TrackerError};
+
+fn read(path: &str) -> Result<Vec<u8>, TrackerError> {
+    std::fs::read(path).map_err(|e| TrackerError::Config(format!("Failed to read {}: {}", path, e)))
+}
+
+/// Builds gRPC server TLS configuration, with client certificate
+/// verification if CA bundle is configured
+pub fn server_tls_config(cfg: &TlsConfig) -> Result<ServerTlsConfig, TrackerError> {
+    let identity = Identity::from_pem(read(&cfg.cert_path)?, read(&cfg.key_path)?);
+    let mut tls = ServerTlsConfig::new().identity(identity);
+    if let Some(ca_path) = &cfg.client_ca_path {
+        tls = tls.client_ca_root(Certificate::from_pem(read(ca_path)?));
+    }
+    Ok(tls)
+}
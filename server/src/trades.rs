@@ -0,0 +1,186 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use strum::{EnumCount, IntoEnumIterator};
+use tokio::sync::{broadcast, mpsc, watch};
+
+use crate::config::TradesConfig;
+use crate::latency::now_us;
+use crate::server::{self as proto, Side, VenueTrades};
+use crate::Exchange;
+
+/// Trades older than the window are dropped at least this often
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Publishes normalized trades and keeps last trade and rolling volume per exchange
+pub struct TradeTape {
+    rx: mpsc::UnboundedReceiver<crate::Trade>,
+    tx: broadcast::Sender<proto::Trade>,
+    stats: watch::Sender<Vec<VenueTrades>>,
+    window_us: u64,
+    /// Receive time, quantity and aggressor of trades in the window, indexed by `Exchange`
+    recent: Vec<VecDeque<(u64, f64, Side)>>,
+    /// Last trade, indexed by `Exchange`
+    last: Vec<Option<proto::Trade>>,
+}
+
+impl TradeTape {
+    pub fn new(
+        cfg: &TradesConfig,
+        rx: mpsc::UnboundedReceiver<crate::Trade>,
+        tx: broadcast::Sender<proto::Trade>,
+    ) -> Self {
+        Self {
+            rx,
+            tx,
+            stats: watch::channel(vec![]).0,
+            window_us: cfg.window_secs as u64 * 1_000_000,
+            recent: vec![VecDeque::new(); Exchange::COUNT],
+            last: vec![None; Exchange::COUNT],
+        }
+    }
+
+    /// Per exchange stats of exchanges that traded
+    pub fn subscribe_stats(&self) -> watch::Receiver<Vec<VenueTrades>> {
+        self.stats.subscribe()
+    }
+
+    pub async fn run(mut self) {
+        let mut ticker = tokio::time::interval(EXPIRE_INTERVAL);
+        loop {
+            tokio::select! {
+                trade = self.rx.recv() => match trade {
+                    Some(trade) => self.on_trade(trade),
+                    // Connectors dropped - app is shutting down
+                    None => break,
+                },
+                _ = ticker.tick() => self.expire(now_us()),
+            }
+        }
+    }
+
+    fn on_trade(&mut self, trade: crate::Trade) {
+        let received = trade.timestamps.received;
+        let msg = proto::Trade {
+            exchange_id: trade.exchange.id() as i32,
+            id: trade.id,
+            price: trade.price,
+            quantity: trade.quantity,
+            aggressor: trade.aggressor as i32,
+            timestamp_us: trade.timestamps.event.unwrap_or(received),
+            received_us: received,
+        };
+        let idx = trade.exchange as usize;
+        self.recent[idx].push_back((received, trade.quantity, trade.aggressor));
+        self.last[idx] = Some(msg.clone());
+        // No receivers is not an error
+        let _ = self.tx.send(msg);
+        self.expire(received);
+    }
+
+    /// Drops trades received before the window and publishes changed stats
+    fn expire(&mut self, now: u64) {
+        let from = now.saturating_sub(self.window_us);
+        for recent in self.recent.iter_mut() {
+            while recent.front().map(|t| t.0 < from).unwrap_or(false) {
+                recent.pop_front();
+            }
+        }
+        let stats = self.stats();
+        self.stats.send_if_modified(|current| {
+            let modified = *current != stats;
+            *current = stats;
+            modified
+        });
+    }
+
+    fn stats(&self) -> Vec<VenueTrades> {
+        Exchange::iter()
+            .filter_map(|e| {
+                let last = self.last[e as usize].as_ref()?;
+                let mut stats = VenueTrades {
+                    exchange_id: last.exchange_id,
+                    last_price: last.price,
+                    last_quantity: last.quantity,
+                    last_aggressor: last.aggressor,
+                    last_timestamp_us: last.timestamp_us,
+                    window_secs: (self.window_us / 1_000_000) as u32,
+                    ..Default::default()
+                };
+                for &(_, quantity, side) in &self.recent[e as usize] {
+                    stats.volume += quantity;
+                    match side {
+                        Side::Buy => stats.buy_volume += quantity,
+                        Side::Sell => stats.sell_volume += quantity,
+                    }
+                    stats.trades += 1;
+                }
+                Some(stats)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TradeTape;
+    use crate::config::TradesConfig;
+    use crate::server::{ExchangeId, Side};
+    use crate::{binance, bitstamp, Trade};
+    use tokio::sync::{broadcast, mpsc};
+
+    #[test]
+    fn test_trades() {
+        let binance: binance::api::StreamMsg = serde_json::from_str(
+            r#"{"stream":"btcusdt@aggTrade","data":{"e":"aggTrade","E":1700000000001,
+            "s":"BTCUSDT","a":7,"p":"100.5","q":"2.0","f":1,"l":2,"T":1700000000000,"m":true,"M":true}}"#,
+        )
+        .unwrap();
+        let binance = match binance.data {
+            binance::api::StreamData::Trade(t) => {
+                Trade::from(t).with_local_timestamps(10_000_000, 0)
+            }
+            _ => panic!("Trade expected"),
+        };
+        assert_eq!(binance.id, 7);
+        assert_eq!(binance.aggressor, Side::Sell);
+        assert_eq!(binance.timestamps.event, Some(1_700_000_000_000_000));
+
+        let bitstamp: bitstamp::api::UpdateMsg = serde_json::from_str(
+            r#"{"event":"trade","channel":"live_trades_btcusd","data":{"id":9,"timestamp":"1700000000",
+            "amount":1.5,"amount_str":"1.5","price":101,"price_str":"101","type":0,
+            "microtimestamp":"1700000000000000","buy_order_id":1,"sell_order_id":2}}"#,
+        )
+        .unwrap();
+        let bitstamp = match bitstamp.data {
+            Some(bitstamp::api::UpdateData::Trade(t)) => {
+                Trade::from(t).with_local_timestamps(70_500_000, 0)
+            }
+            _ => panic!("Trade expected"),
+        };
+        assert_eq!(bitstamp.aggressor, Side::Buy);
+
+        let (_tx, rx) = mpsc::unbounded_channel();
+        let (tx, mut trades) = broadcast::channel(16);
+        let cfg = TradesConfig {
+            enabled: true,
+            window_secs: 60,
+        };
+        let mut tape = TradeTape::new(&cfg, rx, tx);
+        let stats = tape.subscribe_stats();
+        tape.on_trade(binance.clone());
+        tape.on_trade(binance);
+        assert_eq!(trades.try_recv().unwrap().price, 100.5);
+        assert_eq!(stats.borrow()[0].volume, 4.0);
+
+        // First Binance trades fall out of the window
+        tape.on_trade(bitstamp);
+        let stats = stats.borrow();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].volume, 0.0);
+        assert_eq!(stats[0].last_price, 100.5);
+        assert_eq!(stats[1].exchange_id, ExchangeId::Bitstamp as i32);
+        assert_eq!(stats[1].buy_volume, 1.5);
+        assert_eq!(stats[1].trades, 1);
+    }
+}
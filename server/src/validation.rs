@@ -0,0 +1,470 @@
+use strum::EnumCount;
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, info, warn};
+
+use crate::config::{RejectAction, ServerConfig, ValidationConfig};
+use crate::registry::ExchangeRegistry;
+use crate::server::BookRejections;
+use crate::{Bbo, Exchange, Order, OrderBook};
+
+/// Reason a book failed validation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    Crossed,
+    Unsorted,
+    InvalidPrice,
+    InvalidAmount,
+    Outlier,
+}
+
+impl Rejection {
+    pub fn name(self) -> &'static str {
+        match self {
+            Rejection::Crossed => "crossed",
+            Rejection::Unsorted => "unsorted",
+            Rejection::InvalidPrice => "invalid_price",
+            Rejection::InvalidAmount => "invalid_amount",
+            Rejection::Outlier => "outlier",
+        }
+    }
+
+    /// Increments the counter of this reason
+    pub fn count(self, rejections: &mut BookRejections) {
+        let counter = match self {
+            Rejection::Crossed => &mut rejections.crossed,
+            Rejection::Unsorted => &mut rejections.unsorted,
+            Rejection::InvalidPrice => &mut rejections.invalid_price,
+            Rejection::InvalidAmount => &mut rejections.invalid_amount,
+            Rejection::Outlier => &mut rejections.outlier,
+        };
+        *counter += 1;
+    }
+}
+
+fn check_side(orders: &[Order], bids: bool) -> Result<(), Rejection> {
+    for order in orders {
+        if !order.price.is_finite() || order.price <= 0.0 {
+            return Err(Rejection::InvalidPrice);
+        }
+        if !order.quantity.is_finite() || order.quantity <= 0.0 {
+            return Err(Rejection::InvalidAmount);
+        }
+    }
+    let sorted = orders.windows(2).all(|w| {
+        if bids {
+            w[0].price >= w[1].price
+        } else {
+            w[0].price <= w[1].price
+        }
+    });
+    if !sorted {
+        return Err(Rejection::Unsorted);
+    }
+    Ok(())
+}
+
+/// Checks a book on its own: level prices and amounts, level order and crossing
+pub fn check(book: &OrderBook) -> Result<(), Rejection> {
+    check_side(&book.bids, true)?;
+    check_side(&book.asks, false)?;
+    match (book.bids.first(), book.asks.first()) {
+        (Some(bid), Some(ask)) if bid.price >= ask.price => Err(Rejection::Crossed),
+        _ => Ok(()),
+    }
+}
+
+/// Checks a top of book on its own: prices and amounts of quoted sides
+/// and crossing. Zero amounts of a cleared side are valid.
+pub fn check_bbo(bbo: &Bbo) -> Result<(), Rejection> {
+    for &(price, quantity) in &[
+        (bbo.bid_price, bbo.bid_quantity),
+        (bbo.ask_price, bbo.ask_quantity),
+    ] {
+        if !quantity.is_finite() || quantity < 0.0 {
+            return Err(Rejection::InvalidAmount);
+        }
+        if quantity > 0.0 && (!price.is_finite() || price <= 0.0) {
+            return Err(Rejection::InvalidPrice);
+        }
+    }
+    if bbo.bid_quantity > 0.0 && bbo.ask_quantity > 0.0 && bbo.bid_price >= bbo.ask_price {
+        return Err(Rejection::Crossed);
+    }
+    Ok(())
+}
+
+/// Rejects or quarantines malformed and outlier books and BBOs between
+/// connectors and their consumers, counting rejections in the registry
+pub struct BookValidator {
+    rx: mpsc::UnboundedReceiver<OrderBook>,
+    tx: mpsc::UnboundedSender<OrderBook>,
+    bbo_rx: mpsc::UnboundedReceiver<Bbo>,
+    bbo_tx: mpsc::UnboundedSender<Bbo>,
+    registry: ExchangeRegistry,
+    /// Validation settings can change without restart
+    config: watch::Receiver<ServerConfig>,
+    action: RejectAction,
+    /// Maximum deviation from consensus mid as a fraction, 0 if unchecked
+    max_deviation: f64,
+    max_age_us: u64,
+    /// Mid and receive time of the last accepted book, indexed by `Exchange`
+    mids: Vec<Option<(f64, u64)>>,
+    /// Whether the last book was rejected, indexed by `Exchange`
+    rejecting: Vec<bool>,
+    /// Whether the last BBO was rejected, indexed by `Exchange`
+    rejecting_bbo: Vec<bool>,
+}
+
+impl BookValidator {
+    pub fn new(
+        mut config: watch::Receiver<ServerConfig>,
+        rx: mpsc::UnboundedReceiver<OrderBook>,
+        tx: mpsc::UnboundedSender<OrderBook>,
+        bbo_rx: mpsc::UnboundedReceiver<Bbo>,
+        bbo_tx: mpsc::UnboundedSender<Bbo>,
+        registry: ExchangeRegistry,
+    ) -> Self {
+        let cfg = config.borrow_and_update().validation.clone();
+        let mut validator = Self {
+            rx,
+            tx,
+            bbo_rx,
+            bbo_tx,
+            registry,
+            config,
+            action: RejectAction::default(),
+            max_deviation: 0.0,
+            max_age_us: 0,
+            mids: vec![None; Exchange::COUNT],
+            rejecting: vec![false; Exchange::COUNT],
+            rejecting_bbo: vec![false; Exchange::COUNT],
+        };
+        validator.configure(&cfg);
+        validator
+    }
+
+    fn configure(&mut self, cfg: &ValidationConfig) {
+        self.action = cfg.action;
+        self.max_deviation = cfg.max_deviation_bps.max(0.0) / 10_000.0;
+        self.max_age_us = cfg.consensus_max_age_secs * 1_000_000;
+    }
+
+    pub async fn run(mut self) {
+        let mut watching = true;
+        loop {
+            tokio::select! {
+                changed = self.config.changed(), if watching => {
+                    if changed.is_err() {
+                        // Reload disabled
+                        watching = false;
+                        continue;
+                    }
+                    let cfg = self.config.borrow_and_update().validation.clone();
+                    self.configure(&cfg);
+                }
+                book = self.rx.recv() => {
+                    let book = match book {
+                        Some(book) => book,
+                        // Connectors dropped - app is shutting down
+                        None => break,
+                    };
+                    if let Some(book) = self.process(book) {
+                        if self.tx.send(book).is_err() {
+                            // Listener dropped - app is shutting down
+                            break;
+                        }
+                    }
+                }
+                Some(bbo) = self.bbo_rx.recv() => {
+                    if let Some(bbo) = self.process_bbo(bbo) {
+                        // BBO merger dropped - app is shutting down
+                        let _ = self.bbo_tx.send(bbo);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Book to forward to the listener, if any
+    fn process(&mut self, book: OrderBook) -> Option<OrderBook> {
+        let exchange = book.exchange;
+        let idx = exchange as usize;
+        match self.validate(&book) {
+            Ok(()) => {
+                if self.rejecting[idx] {
+                    info!(exchange = exchange.name(), "Valid book received");
+                    self.rejecting[idx] = false;
+                }
+                Some(book)
+            }
+            Err(reason) => {
+                self.mids[idx] = None;
+                self.reject(exchange, reason, false);
+                match self.action {
+                    RejectAction::Reject => None,
+                    // Empty book removes the exchange from the merge
+                    RejectAction::Quarantine => Some(OrderBook {
+                        exchange,
+                        timestamps: book.timestamps,
+                        ..Default::default()
+                    }),
+                }
+            }
+        }
+    }
+
+    /// BBO to forward to the merger, if any. Checked against the consensus
+    /// mid of books as BBO feeds are not kept for consensus.
+    fn process_bbo(&mut self, bbo: Bbo) -> Option<Bbo> {
+        let exchange = bbo.exchange;
+        let idx = exchange as usize;
+        match self.validate_bbo(&bbo) {
+            Ok(()) => {
+                if self.rejecting_bbo[idx] {
+                    info!(exchange = exchange.name(), "Valid BBO received");
+                    self.rejecting_bbo[idx] = false;
+                }
+                Some(bbo)
+            }
+            Err(reason) => {
+                self.reject(exchange, reason, true);
+                match self.action {
+                    RejectAction::Reject => None,
+                    // Cleared BBO removes the exchange from the merge
+                    RejectAction::Quarantine => Some(Bbo::cleared(exchange)),
+                }
+            }
+        }
+    }
+
+    /// Counts the rejection, logged once until valid data arrives
+    fn reject(&mut self, exchange: Exchange, reason: Rejection, bbo: bool) {
+        self.registry.reject(exchange, reason);
+        let (rejecting, kind) = if bbo {
+            (&mut self.rejecting_bbo[exchange as usize], "BBO")
+        } else {
+            (&mut self.rejecting[exchange as usize], "book")
+        };
+        if *rejecting {
+            debug!(
+                exchange = exchange.name(),
+                reason = reason.name(),
+                kind,
+                "Rejected"
+            );
+        } else {
+            warn!(
+                exchange = exchange.name(),
+                reason = reason.name(),
+                action = ?self.action,
+                kind,
+                "Rejected"
+            );
+            *rejecting = true;
+        }
+    }
+
+    fn validate_bbo(&self, bbo: &Bbo) -> Result<(), Rejection> {
+        check_bbo(bbo)?;
+        if let Some(mid) = self.consensus(bbo.exchange, bbo.timestamps.received) {
+            let deviates = |price: f64, quantity: f64| {
+                quantity > 0.0 && ((price - mid) / mid).abs() > self.max_deviation
+            };
+            if deviates(bbo.bid_price, bbo.bid_quantity)
+                || deviates(bbo.ask_price, bbo.ask_quantity)
+            {
+                return Err(Rejection::Outlier);
+            }
+        }
+        Ok(())
+    }
+
+    fn validate(&mut self, book: &OrderBook) -> Result<(), Rejection> {
+        check(book)?;
+        let now = book.timestamps.received;
+        if let Some(mid) = self.consensus(book.exchange, now) {
+            let deviates = |o: Option<&Order>| {
+                o.map(|o| ((o.price - mid) / mid).abs() > self.max_deviation)
+                    .unwrap_or(false)
+            };
+            if deviates(book.bids.first()) || deviates(book.asks.first()) {
+                return Err(Rejection::Outlier);
+            }
+        }
+        self.mids[book.exchange as usize] = book
+            .bids
+            .first()
+            .zip(book.asks.first())
+            .map(|(b, a)| ((b.price + a.price) / 2.0, now));
+        Ok(())
+    }
+
+    /// Median mid of other exchanges with recent valid books
+    fn consensus(&self, exchange: Exchange, now: u64) -> Option<f64> {
+        if self.max_deviation == 0.0 {
+            return None;
+        }
+        let mut mids: Vec<f64> = self
+            .mids
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != exchange as usize)
+            .filter_map(|(_, m)| *m)
+            .filter(|(_, at)| now.saturating_sub(*at) <= self.max_age_us)
+            .map(|(mid, _)| mid)
+            .collect();
+        if mids.is_empty() {
+            return None;
+        }
+        mids.sort_by(|a, b| a.total_cmp(b));
+        let n = mids.len();
+        Some(if n % 2 == 1 {
+            mids[n / 2]
+        } else {
+            (mids[n / 2 - 1] + mids[n / 2]) / 2.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, check_bbo, BookValidator, Rejection};
+    use crate::config::ServerConfig;
+    use crate::registry::ExchangeRegistry;
+    use crate::{Bbo, Exchange, Order, OrderBook, Timestamps};
+    use tokio::sync::{mpsc, watch};
+
+    fn book(exchange: Exchange, bids: &[(f64, f64)], asks: &[(f64, f64)]) -> OrderBook {
+        let orders = |levels: &[(f64, f64)]| {
+            levels
+                .iter()
+                .map(|&(price, quantity)| Order {
+                    price,
+                    quantity,
+                    id: 0,
+                    exchange,
+                })
+                .collect()
+        };
+        OrderBook {
+            exchange,
+            bids: orders(bids),
+            asks: orders(asks),
+            timestamps: Timestamps {
+                received: 1_000_000,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let b = Exchange::Binance;
+        assert_eq!(
+            check(&book(b, &[(100.0, 1.0), (99.0, 1.0)], &[(101.0, 1.0)])),
+            Ok(())
+        );
+        assert_eq!(
+            check(&book(b, &[(101.0, 1.0)], &[(100.0, 1.0)])),
+            Err(Rejection::Crossed)
+        );
+        assert_eq!(
+            check(&book(b, &[(99.0, 1.0), (100.0, 1.0)], &[])),
+            Err(Rejection::Unsorted)
+        );
+        assert_eq!(
+            check(&book(b, &[], &[(0.0, 1.0)])),
+            Err(Rejection::InvalidPrice)
+        );
+        assert_eq!(
+            check(&book(b, &[(100.0, 0.0)], &[])),
+            Err(Rejection::InvalidAmount)
+        );
+
+        let cfg: ServerConfig = serde_yaml::from_str(
+            r#"
+grpc_listen_addr: 127.0.0.1:0
+binance: { symbol: BTCUSDT }
+bitstamp: { symbol: BTCUSDT }
+validation: { max_deviation_bps: 100 }
+"#,
+        )
+        .unwrap();
+        let registry = ExchangeRegistry::new(&cfg);
+        let (_tx, rx) = mpsc::unbounded_channel();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let (_bbo_tx, bbo_rx) = mpsc::unbounded_channel();
+        let (bbo_tx, _bbo_rx) = mpsc::unbounded_channel();
+        let (_config_tx, config) = watch::channel(cfg);
+        let mut validator = BookValidator::new(config, rx, tx, bbo_rx, bbo_tx, registry.clone());
+
+        // No consensus yet
+        let first = book(b, &[(100.0, 1.0)], &[(101.0, 1.0)]);
+        assert!(validator.process(first).is_some());
+
+        // Fat finger ask 5% below the market quarantines Bitstamp
+        let s = Exchange::Bitstamp;
+        let quarantined = validator.process(book(s, &[(94.0, 1.0)], &[(95.0, 1.0)]));
+        assert!(quarantined.unwrap().asks.is_empty());
+        assert!(validator
+            .process(book(s, &[(100.0, 1.0)], &[(101.0, 2.0)]))
+            .is_some());
+        assert!(validator
+            .process(book(s, &[(100.0, -1.0)], &[]))
+            .unwrap()
+            .bids
+            .is_empty());
+
+        let rejections = registry.exchanges()[s as usize].rejections.clone().unwrap();
+        assert_eq!((rejections.outlier, rejections.invalid_amount), (1, 1));
+    }
+
+    #[test]
+    fn test_validate_bbo() {
+        let bbo = |bid_price, ask_price| Bbo {
+            exchange: Exchange::Bitstamp,
+            bid_price,
+            bid_quantity: 1.0,
+            ask_price,
+            ask_quantity: 1.0,
+            timestamps: Timestamps {
+                received: 1_000_000,
+                ..Default::default()
+            },
+        };
+        assert_eq!(check_bbo(&bbo(100.0, 101.0)), Ok(()));
+        assert_eq!(check_bbo(&bbo(101.0, 100.0)), Err(Rejection::Crossed));
+        assert_eq!(check_bbo(&bbo(-1.0, 100.0)), Err(Rejection::InvalidPrice));
+        assert_eq!(check_bbo(&Bbo::cleared(Exchange::Bitstamp)), Ok(()));
+
+        let cfg: ServerConfig = serde_yaml::from_str(
+            r#"
+grpc_listen_addr: 127.0.0.1:0
+binance: { symbol: BTCUSDT }
+bitstamp: { symbol: BTCUSDT }
+validation: { max_deviation_bps: 100 }
+"#,
+        )
+        .unwrap();
+        let registry = ExchangeRegistry::new(&cfg);
+        let (_tx, rx) = mpsc::unbounded_channel();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let (_bbo_tx, bbo_rx) = mpsc::unbounded_channel();
+        let (bbo_tx, _bbo_rx) = mpsc::unbounded_channel();
+        let (_config_tx, config) = watch::channel(cfg);
+        let mut validator = BookValidator::new(config, rx, tx, bbo_rx, bbo_tx, registry.clone());
+
+        // Consensus from the Binance book
+        let binance = book(Exchange::Binance, &[(100.0, 1.0)], &[(101.0, 1.0)]);
+        assert!(validator.process(binance).is_some());
+        assert!(validator.process_bbo(bbo(100.0, 101.0)).is_some());
+        let quarantined = validator.process_bbo(bbo(94.0, 95.0)).unwrap();
+        assert_eq!(quarantined.ask_quantity, 0.0);
+        let rejections = registry.exchanges()[Exchange::Bitstamp as usize]
+            .rejections
+            .clone()
+            .unwrap();
+        assert_eq!(rejections.outlier, 1);
+    }
+}